@@ -0,0 +1,244 @@
+//! The dependency graph between the Addressables bundles of a game version.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use rabex::files::bundlefile::{BundleFileReader, ExtractionConfig};
+use rabex_env::rabex::UnityVersion;
+use rabex_env::rabex::files::SerializedFile;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rustc_hash::FxHashMap;
+use serde_derive::Serialize;
+
+use crate::diff::addressables::{self, Catalog};
+use crate::old_new::OldNew;
+
+#[derive(Default, Serialize)]
+pub struct Graph {
+    /// Bundle -> bundles its serialized files reference through `m_Externals`
+    pub bundles: BTreeMap<String, BTreeSet<String>>,
+    /// Addressable asset -> bundles it is loaded from, according to the catalog
+    pub assets: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The serialized files a bundle contains and the files they reference
+struct BundleFiles {
+    name: String,
+    cabs: Vec<String>,
+    externals: Vec<String>,
+}
+
+impl Graph {
+    /// Builds the graph from the addressables build in `StreamingAssets/aa`.
+    /// Bundles are named by their path below that directory, without their hash.
+    pub fn build<'a>(
+        dir: &Path,
+        paths: impl IntoIterator<Item = &'a str>,
+        unity_version: UnityVersion,
+    ) -> Result<Graph> {
+        let mut aa_dir = None;
+        let mut bundle_paths = Vec::new();
+        for path in paths {
+            let normalized = path.replace('\\', "/");
+            let Some((prefix, rest)) = normalized.split_once("StreamingAssets/aa/") else {
+                continue;
+            };
+            if rest == "catalog.bin" {
+                aa_dir = Some(format!("{prefix}StreamingAssets/aa"));
+            } else if rest.ends_with(".bundle") {
+                bundle_paths.push((path, addressables::bundle_name(rest)));
+            }
+        }
+
+        let bundles = bundle_paths
+            .into_par_iter()
+            .map(|(path, name)| {
+                read_bundle(&dir.join(path), name, unity_version)
+                    .with_context(|| format!("failed to read bundle {path}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut graph = Graph::default();
+        let mut cab_bundles = FxHashMap::default();
+        for bundle in &bundles {
+            graph.bundles.insert(bundle.name.clone(), BTreeSet::new());
+            for cab in &bundle.cabs {
+                cab_bundles.insert(cab.as_str(), bundle.name.as_str());
+            }
+        }
+        for bundle in &bundles {
+            let references = graph.bundles.get_mut(&bundle.name).unwrap();
+            for external in &bundle.externals {
+                // archive:/CAB-<hash>/CAB-<hash>
+                let Some(cab) = external
+                    .strip_prefix("archive:/")
+                    .and_then(|path| path.split('/').next())
+                else {
+                    continue;
+                };
+                if let Some(&target) = cab_bundles.get(cab)
+                    && target != bundle.name
+                {
+                    references.insert(target.to_owned());
+                }
+            }
+        }
+
+        if let Some(aa_dir) = aa_dir {
+            let data = std::fs::read(dir.join(&aa_dir).join("catalog.bin"))?;
+            let catalog = Catalog::read(&data).context("failed to read addressables catalog")?;
+            for location in &catalog.locations {
+                if let Some(name) = location.bundle_name() {
+                    graph.bundles.entry(name).or_default();
+                } else if !location.dependencies.is_empty() {
+                    graph
+                        .assets
+                        .entry(location.primary_key.clone())
+                        .or_default()
+                        .extend(catalog.bundle_dependencies(location));
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Bundles that neither an asset nor another bundle depends on
+    pub fn orphans(&self) -> BTreeSet<&str> {
+        let referenced: BTreeSet<&str> = self
+            .bundles
+            .values()
+            .chain(self.assets.values())
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        self.bundles
+            .keys()
+            .map(String::as_str)
+            .filter(|bundle| !referenced.contains(bundle))
+            .collect()
+    }
+
+    pub fn to_dot(&self) -> Result<String> {
+        let mut out = String::new();
+        writeln!(out, "digraph dependencies {{")?;
+        writeln!(out, "  rankdir=LR;")?;
+        writeln!(out, "  node [shape=box];")?;
+        for orphan in self.orphans() {
+            writeln!(out, "  {orphan:?} [color=red];")?;
+        }
+        for (bundle, references) in &self.bundles {
+            for reference in references {
+                writeln!(out, "  {bundle:?} -> {reference:?};")?;
+            }
+        }
+        for (asset, bundles) in &self.assets {
+            writeln!(out, "  {asset:?} [shape=ellipse];")?;
+            for bundle in bundles {
+                writeln!(out, "  {asset:?} -> {bundle:?} [style=dashed];")?;
+            }
+        }
+        writeln!(out, "}}")?;
+        Ok(out)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["orphans"] = serde_json::to_value(self.orphans())?;
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+}
+
+fn read_bundle(path: &Path, name: String, unity_version: UnityVersion) -> Result<BundleFiles> {
+    let data = std::fs::read(path)?;
+    let config = ExtractionConfig::new(None, Some(unity_version));
+    let bundle = BundleFileReader::from_reader(Cursor::new(data.as_slice()), &config)?;
+
+    let mut cabs = Vec::new();
+    let mut externals = Vec::new();
+    for file in bundle.files() {
+        if file.path.ends_with(".resS") || file.path.ends_with("resource") {
+            continue;
+        }
+        let data = bundle
+            .read_at(&file.path)?
+            .context("missing bundle entry")?;
+        let serialized = SerializedFile::from_reader(&mut Cursor::new(data.as_slice()))?;
+        externals.extend(
+            serialized
+                .m_Externals
+                .iter()
+                .map(|external| external.pathName.clone()),
+        );
+        cabs.push(file.path.clone());
+    }
+
+    Ok(BundleFiles {
+        name,
+        cabs,
+        externals,
+    })
+}
+
+pub fn diff(graph: OldNew<&Graph>) -> Result<String> {
+    let mut out = String::new();
+    diff_nodes(&mut out, "Bundles", graph.map(|graph| &graph.bundles))?;
+    diff_nodes(&mut out, "Assets", graph.map(|graph| &graph.assets))?;
+
+    let orphans = graph.map(Graph::orphans);
+    let orphans = orphans.as_ref().changes(|orphans| orphans.iter().copied());
+    if !orphans.removed.is_empty() || !orphans.added.is_empty() {
+        writeln!(out, "--- Orphaned bundles ---")?;
+        for bundle in orphans.removed {
+            writeln!(out, "- {bundle}")?;
+        }
+        for bundle in orphans.added {
+            writeln!(out, "+ {bundle}")?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Writes added and removed nodes, then the changed edges of the nodes in both graphs.
+fn diff_nodes(
+    out: &mut String,
+    title: &str,
+    nodes: OldNew<&BTreeMap<String, BTreeSet<String>>>,
+) -> Result<()> {
+    let changes = nodes.changes(|nodes| nodes.keys().map(String::as_str));
+    if !changes.removed.is_empty() || !changes.added.is_empty() {
+        writeln!(out, "--- {title} ---")?;
+        for node in &changes.removed {
+            writeln!(out, "- {node}")?;
+        }
+        for node in &changes.added {
+            writeln!(out, "+ {node}")?;
+        }
+    }
+
+    let mut section = String::new();
+    for node in changes.same {
+        let edges = nodes.map(|nodes| &nodes[node]);
+        let edges = edges.changes(|edges| edges.iter().map(String::as_str));
+        if edges.removed.is_empty() && edges.added.is_empty() {
+            continue;
+        }
+        writeln!(section, "{node}")?;
+        for target in edges.removed {
+            writeln!(section, "  - {target}")?;
+        }
+        for target in edges.added {
+            writeln!(section, "  + {target}")?;
+        }
+    }
+    if !section.is_empty() {
+        writeln!(out, "--- {title} dependencies ---")?;
+        out.push_str(&section);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,515 @@
+//! Unity Addressables' binary content catalog (`catalog.bin`), as written by `BinaryStorageBuffer`.
+//!
+//! Everything in the buffer is referenced by its offset. Arrays and strings are prefixed by their byte length,
+//! the top bits of a string offset mark UTF-16 strings and strings split into parts at a separator.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use anyhow::{Context as _, Result, ensure};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+
+use crate::old_new::OldNew;
+
+/// `ContentCatalogData.kMagic`
+const MAGIC: u32 = 0x0de3_8942;
+/// Catalog versions with the header layout below, version 2 appends the build result hash
+const VERSIONS: [u32; 2] = [1, 2];
+
+const NULL: u32 = u32::MAX;
+const UNICODE_STRING: u32 = 0x8000_0000;
+const DYNAMIC_STRING: u32 = 0x4000_0000;
+const CLEAR_FLAGS: u32 = 0x3fff_ffff;
+
+/// Hashes added to bundle names by `BundleNamingStyle.AppendHash` and friends
+static BUNDLE_HASH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("^(.*/)?(?:[0-9a-f]{32}_)?(.*?)(?:_[0-9a-f]{32})?\\.bundle$").unwrap()
+});
+
+/// The path of a bundle below the addressables build directory without the hash, which stays the same across builds,
+/// e.g. `StandaloneLinux64/fonts_assets_.bundle`
+pub fn bundle_name(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    // `{UnityEngine.AddressableAssets.Addressables.RuntimePath}/StandaloneLinux64/...`
+    let path = path
+        .rsplit_once('}')
+        .map_or(path.as_str(), |(_, path)| path)
+        .trim_start_matches('/');
+    match BUNDLE_HASH.captures(path) {
+        Some(captures) => format!(
+            "{}{}.bundle",
+            captures.get(1).map_or("", |dir| dir.as_str()),
+            &captures[2]
+        ),
+        None => path.to_owned(),
+    }
+}
+
+pub struct Catalog {
+    pub id: String,
+    pub locations: Vec<Location>,
+    /// key -> indices into `locations`
+    pub keys: BTreeMap<String, Vec<usize>>,
+}
+
+pub struct Location {
+    pub primary_key: String,
+    pub internal_id: String,
+    pub provider: String,
+    /// Indices into [`Catalog::locations`]
+    pub dependencies: Vec<usize>,
+}
+
+impl Location {
+    pub fn is_bundle(&self) -> bool {
+        self.provider.ends_with("AssetBundleProvider")
+    }
+
+    pub fn bundle_name(&self) -> Option<String> {
+        self.is_bundle().then(|| bundle_name(&self.internal_id))
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.internal_id
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(&self.internal_id)
+    }
+}
+
+impl Catalog {
+    pub fn read(data: &[u8]) -> Result<Catalog> {
+        let reader = Reader { data };
+        // magic, version, keysOffset, idOffset, instanceProviderOffset, sceneProviderOffset, ...
+        let magic = reader.u32(0)?;
+        ensure!(magic == MAGIC, "not a binary catalog (magic {magic:#010x})");
+        let version = reader.u32(4)?;
+        ensure!(
+            VERSIONS.contains(&version),
+            "unsupported catalog version {version}"
+        );
+        let keys_offset = reader.u32(8)?;
+        let id = reader
+            .string(reader.u32(12)?, '/')
+            .context("failed to read catalog id")?;
+
+        let mut catalog = Catalog {
+            id,
+            locations: Vec::new(),
+            keys: BTreeMap::new(),
+        };
+        let mut location_indices = FxHashMap::default();
+
+        let keys = reader.array(keys_offset)?;
+        for key_data in keys.chunks_exact(8) {
+            let key_name = u32::from_le_bytes(key_data[0..4].try_into().unwrap());
+            let location_set = u32::from_le_bytes(key_data[4..8].try_into().unwrap());
+
+            let key = reader.object(key_name).context("failed to read key")?;
+            let mut locations = Vec::new();
+            for location in reader.u32_array(location_set)? {
+                locations.push(catalog.location(&reader, &mut location_indices, location)?);
+            }
+            catalog.keys.entry(key).or_default().extend(locations);
+        }
+
+        Ok(catalog)
+    }
+
+    fn location(
+        &mut self,
+        reader: &Reader,
+        indices: &mut FxHashMap<u32, usize>,
+        offset: u32,
+    ) -> Result<usize> {
+        if let Some(&index) = indices.get(&offset) {
+            return Ok(index);
+        }
+
+        // primaryKey, internalId, provider, dependencySet, dependencyHash, extraData, type
+        let primary_key = reader.string(reader.u32(offset)?, '/')?;
+        let internal_id = reader.string(reader.field(offset, 1)?, '/')?;
+        let provider = reader.string(reader.field(offset, 2)?, '.')?;
+        let dependency_set = reader.field(offset, 3)?;
+
+        let index = self.locations.len();
+        indices.insert(offset, index);
+        self.locations.push(Location {
+            primary_key,
+            internal_id,
+            provider,
+            dependencies: Vec::new(),
+        });
+
+        let mut dependencies = Vec::new();
+        for dependency in reader.u32_array(dependency_set)? {
+            dependencies.push(self.location(reader, indices, dependency)?);
+        }
+        self.locations[index].dependencies = dependencies;
+
+        Ok(index)
+    }
+
+    /// Bundle name -> file name
+    pub fn bundles(&self) -> BTreeMap<String, &str> {
+        self.locations
+            .iter()
+            .filter_map(|location| Some((location.bundle_name()?, location.file_name())))
+            .collect()
+    }
+
+    /// The bundles a location needs, by name
+    pub fn bundle_dependencies(&self, location: &Location) -> BTreeSet<String> {
+        location
+            .dependencies
+            .iter()
+            .filter_map(|&dependency| self.locations[dependency].bundle_name())
+            .collect()
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn u32(&self, offset: u32) -> Result<u32> {
+        self.field(offset, 0)
+    }
+
+    /// The `index`th `u32` of the struct at `offset`
+    fn field(&self, offset: u32, index: usize) -> Result<u32> {
+        let offset = offset as usize + index * 4;
+        let bytes = self
+            .data
+            .get(offset..offset + 4)
+            .with_context(|| format!("offset {offset} out of bounds"))?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn array(&self, offset: u32) -> Result<&[u8]> {
+        if offset == NULL {
+            return Ok(&[]);
+        }
+        ensure!(offset >= 4, "offset {offset} out of bounds");
+        let len = self.u32(offset - 4)? as usize;
+        let start = offset as usize;
+        self.data
+            .get(start..start + len)
+            .with_context(|| format!("array at {offset} out of bounds"))
+    }
+
+    fn u32_array(&self, offset: u32) -> Result<Vec<u32>> {
+        Ok(self
+            .array(offset)?
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect())
+    }
+
+    fn string(&self, offset: u32, separator: char) -> Result<String> {
+        if offset == NULL {
+            return Ok(String::new());
+        }
+        if offset & DYNAMIC_STRING == 0 {
+            return self.encoded_string(offset);
+        }
+
+        // linked list of (part, next)
+        let mut string = String::new();
+        let mut part = offset & CLEAR_FLAGS;
+        for i in 0.. {
+            ensure!(i < 4096, "string parts at {offset} form a cycle");
+            if i > 0 {
+                string.push(separator);
+            }
+            string.push_str(&self.encoded_string(self.u32(part)?)?);
+            part = self.field(part, 1)?;
+            if part == NULL {
+                break;
+            }
+        }
+        Ok(string)
+    }
+
+    fn encoded_string(&self, offset: u32) -> Result<String> {
+        if offset & UNICODE_STRING != 0 {
+            let utf16: Vec<u16> = self
+                .array(offset & CLEAR_FLAGS)?
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect();
+            Ok(String::from_utf16(&utf16)?)
+        } else {
+            Ok(String::from_utf8(self.array(offset)?.to_vec())?)
+        }
+    }
+
+    /// Reads a key, which is stored with its type. Strings and integers are rendered as is, other keys by their type.
+    fn object(&self, offset: u32) -> Result<String> {
+        let (type_offset, object) = (self.u32(offset)?, self.field(offset, 1)?);
+        let class = self.string(self.field(type_offset, 1)?, '.')?;
+        Ok(match class.as_str() {
+            "System.String" => self.string(object, '/')?,
+            "System.Int32" => (self.u32(object)? as i32).to_string(),
+            _ => format!("<{class}>"),
+        })
+    }
+}
+
+pub fn diff_catalog(data: OldNew<&[u8]>) -> Result<String> {
+    let catalog = data.try_map(Catalog::read)?;
+    let catalog = catalog.as_ref();
+    let mut out = String::new();
+
+    if catalog.old.id != catalog.new.id {
+        writeln!(
+            out,
+            "catalog id '{}' -> '{}'",
+            catalog.old.id, catalog.new.id
+        )?;
+    }
+
+    let keys = catalog.changes(|catalog| catalog.keys.keys().map(String::as_str));
+    if !keys.removed.is_empty() || !keys.added.is_empty() {
+        writeln!(out, "--- Keys ---")?;
+        for key in keys.removed {
+            writeln!(out, "- {key}")?;
+        }
+        for key in keys.added {
+            writeln!(out, "+ {key}")?;
+        }
+    }
+
+    let bundles = catalog.map(Catalog::bundles);
+    let bundles = bundles.as_ref();
+    let bundle_changes = bundles.changes(|bundles| bundles.keys().map(String::as_str));
+    let rebuilt: Vec<_> = bundle_changes
+        .same
+        .iter()
+        .map(|&name| (name, bundles.map(|bundles| bundles[name])))
+        .filter(|(_, file)| file.changed())
+        .collect();
+    if !bundle_changes.removed.is_empty() || !bundle_changes.added.is_empty() || !rebuilt.is_empty()
+    {
+        writeln!(out, "--- Bundles ---")?;
+        for name in bundle_changes.removed {
+            writeln!(out, "- {}", bundles.old[name])?;
+        }
+        for name in bundle_changes.added {
+            writeln!(out, "+ {}", bundles.new[name])?;
+        }
+        for (_, file) in rebuilt {
+            writeln!(out, "~ {} -> {}", file.old, file.new)?;
+        }
+    }
+
+    let dependencies = catalog.map(|catalog| {
+        catalog
+            .locations
+            .iter()
+            .filter(|location| !location.dependencies.is_empty())
+            .map(|location| {
+                let name = location
+                    .bundle_name()
+                    .unwrap_or_else(|| location.primary_key.clone());
+                (name, catalog.bundle_dependencies(location))
+            })
+            .collect::<BTreeMap<_, _>>()
+    });
+    let dependencies = dependencies.as_ref();
+    let mut section = String::new();
+    for name in dependencies
+        .changes(|dependencies| dependencies.keys().map(String::as_str))
+        .same
+    {
+        let bundles = dependencies.map(|dependencies| &dependencies[name]);
+        let changes = bundles.changes(|bundles| bundles.iter().map(String::as_str));
+        if changes.removed.is_empty() && changes.added.is_empty() {
+            continue;
+        }
+        writeln!(section, "{name}")?;
+        for bundle in changes.removed {
+            writeln!(section, "  - {bundle}")?;
+        }
+        for bundle in changes.added {
+            writeln!(section, "  + {bundle}")?;
+        }
+    }
+    if !section.is_empty() {
+        writeln!(out, "--- Dependencies ---")?;
+        out.push_str(&section);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a catalog buffer the way `BinaryStorageBuffer` lays it out
+    struct Buffer {
+        data: Vec<u8>,
+    }
+
+    impl Buffer {
+        /// Starts with a header pointing at nothing
+        fn new() -> Buffer {
+            let mut buffer = Buffer { data: Vec::new() };
+            for value in [MAGIC, 2, NULL, NULL, NULL, NULL, NULL, NULL] {
+                buffer.data.extend(value.to_le_bytes());
+            }
+            buffer
+        }
+
+        fn set(&mut self, offset: u32, index: usize, value: u32) {
+            let offset = offset as usize + index * 4;
+            self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fn array(&mut self, bytes: &[u8]) -> u32 {
+            self.data.extend((bytes.len() as u32).to_le_bytes());
+            let offset = self.data.len() as u32;
+            self.data.extend(bytes);
+            while self.data.len() % 4 != 0 {
+                self.data.push(0);
+            }
+            offset
+        }
+
+        fn u32s(&mut self, values: &[u32]) -> u32 {
+            let bytes: Vec<u8> = values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            self.array(&bytes)
+        }
+
+        fn ascii(&mut self, string: &str) -> u32 {
+            self.array(string.as_bytes())
+        }
+
+        fn utf16(&mut self, string: &str) -> u32 {
+            let bytes: Vec<u8> = string.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            self.array(&bytes) | UNICODE_STRING
+        }
+
+        /// A string split into parts, as a linked list of `(part, next)`
+        fn dynamic(&mut self, parts: &[&str]) -> u32 {
+            let mut next = NULL;
+            for part in parts.iter().rev() {
+                let part = self.ascii(part);
+                next = self.u32s(&[part, next]);
+            }
+            next | DYNAMIC_STRING
+        }
+
+        fn key(&mut self, class: &str, object: u32) -> u32 {
+            let assembly = self.ascii("mscorlib");
+            let class = self.ascii(class);
+            let key_type = self.u32s(&[assembly, class]);
+            self.u32s(&[key_type, object])
+        }
+
+        fn location(&mut self, primary_key: &str, internal_id: &str, provider: &str) -> u32 {
+            let primary_key = self.ascii(primary_key);
+            let internal_id = self.dynamic(&internal_id.split('/').collect::<Vec<_>>());
+            let provider = self.dynamic(&provider.split('.').collect::<Vec<_>>());
+            self.u32s(&[primary_key, internal_id, provider, NULL, 0, NULL, NULL])
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let mut buffer = Buffer::new();
+        let utf16 = buffer.utf16("Hornet's Nadel ✓");
+        let dynamic = buffer.dynamic(&["Assets", "Prefabs", "Hornet.prefab"]);
+        let reader = Reader { data: &buffer.data };
+
+        assert_eq!(reader.string(utf16, '/').unwrap(), "Hornet's Nadel ✓");
+        assert_eq!(
+            reader.string(dynamic, '/').unwrap(),
+            "Assets/Prefabs/Hornet.prefab"
+        );
+        assert_eq!(
+            reader.string(dynamic, '.').unwrap(),
+            "Assets.Prefabs.Hornet.prefab"
+        );
+        assert_eq!(reader.string(NULL, '/').unwrap(), "");
+    }
+
+    #[test]
+    fn catalog() {
+        let mut buffer = Buffer::new();
+        let id = buffer.dynamic(&["AddressablesMainContentCatalog"]);
+        buffer.set(0, 3, id);
+
+        let provider = "UnityEngine.ResourceManagement.ResourceProviders.AssetBundleProvider";
+        let hornet = buffer.location(
+            "hornet",
+            "{UnityEngine.AddressableAssets.Addressables.RuntimePath}/StandaloneLinux64/hornet_assets_all_0123456789abcdef0123456789abcdef.bundle",
+            provider,
+        );
+        let shared = buffer.location(
+            "shared",
+            "{UnityEngine.AddressableAssets.Addressables.RuntimePath}/StandaloneLinux64/shared_assets_all_fedcba9876543210fedcba9876543210.bundle",
+            provider,
+        );
+        // bundles can depend on each other
+        let dependencies = buffer.u32s(&[shared]);
+        buffer.set(hornet, 3, dependencies);
+        let dependencies = buffer.u32s(&[hornet]);
+        buffer.set(shared, 3, dependencies);
+
+        let name = buffer.ascii("hornet");
+        let string_key = buffer.key("System.String", name);
+        let number = buffer.u32s(&[(-7i32) as u32]);
+        let int_key = buffer.key("System.Int32", number);
+        let hash_key = buffer.key("UnityEngine.Hash128", NULL);
+        let hornet_set = buffer.u32s(&[hornet]);
+        let both_set = buffer.u32s(&[hornet, shared]);
+        let keys = buffer.u32s(&[
+            string_key, hornet_set, int_key, both_set, hash_key, both_set,
+        ]);
+        buffer.set(0, 2, keys);
+
+        let catalog = Catalog::read(&buffer.data).unwrap();
+        assert_eq!(catalog.id, "AddressablesMainContentCatalog");
+        assert_eq!(catalog.keys["hornet"], [0]);
+        assert_eq!(catalog.keys["-7"], [0, 1]);
+        assert_eq!(catalog.keys["<UnityEngine.Hash128>"], [0, 1]);
+
+        assert_eq!(catalog.locations.len(), 2);
+        assert_eq!(catalog.locations[0].dependencies, [1]);
+        assert_eq!(catalog.locations[1].dependencies, [0]);
+        assert_eq!(
+            catalog.bundles().into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "StandaloneLinux64/hornet_assets_all.bundle".to_owned(),
+                    "hornet_assets_all_0123456789abcdef0123456789abcdef.bundle"
+                ),
+                (
+                    "StandaloneLinux64/shared_assets_all.bundle".to_owned(),
+                    "shared_assets_all_fedcba9876543210fedcba9876543210.bundle"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn header() {
+        let mut buffer = Buffer::new();
+        buffer.set(0, 1, 3);
+        let error = Catalog::read(&buffer.data).err().unwrap();
+        assert_eq!(error.to_string(), "unsupported catalog version 3");
+
+        buffer.set(0, 0, 0x1234_5678);
+        let error = Catalog::read(&buffer.data).err().unwrap();
+        assert_eq!(error.to_string(), "not a binary catalog (magic 0x12345678)");
+        assert!(Catalog::read(&[0; 2]).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! `key=value` files like Unity's `boot.config`, diffed by key instead of by line.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use anyhow::{Context as _, Result, bail};
+
+use crate::old_new::OldNew;
+
+/// What the player options in `boot.config` do. Entries ending in `-` annotate every key with that prefix.
+const BOOT_KEYS: &[(&str, &str)] = &[
+    ("gfx-enable-gfx-jobs", "graphics jobs"),
+    ("gfx-enable-native-gfx-jobs", "native graphics jobs"),
+    (
+        "gfx-disable-mt-rendering",
+        "disables multithreaded rendering",
+    ),
+    (
+        "scripting-runtime-version",
+        "scripting runtime, `latest` is .NET 4.x",
+    ),
+    (
+        "wait-for-native-debugger",
+        "waits for a native debugger on startup",
+    ),
+    (
+        "wait-for-managed-debugger",
+        "waits for a managed debugger on startup",
+    ),
+    ("player-connection-debug", "allows the profiler to connect"),
+    ("single-instance", "only allows one running instance"),
+    ("hdr-display-enabled", "HDR display output"),
+    ("vr-enabled", "VR support"),
+    ("gc-max-time-slice", "incremental GC time slice in ms"),
+    ("job-worker-count", "job system worker threads"),
+    (
+        "job-worker-maximum-count",
+        "maximum job system worker threads",
+    ),
+    ("headless", "runs without graphics"),
+    ("memorysetup-", "memory allocator settings"),
+    ("profiler-", "profiler settings"),
+];
+
+/// Parses `key=value` lines, skipping comments and prefixing keys with their `[section]`.
+/// Repeated keys get `#2`, `#3`, etc. appended, so that every occurrence is diffed.
+fn parse(text: &str) -> Result<BTreeMap<String, &str>> {
+    let mut entries = BTreeMap::new();
+    let mut seen = HashMap::<String, usize>::new();
+    let mut section = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = Some(name);
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("not a key=value line: {line}");
+        };
+        let key = match section {
+            Some(section) => format!("{section}.{}", key.trim()),
+            None => key.trim().to_owned(),
+        };
+        let index = seen.entry(key.clone()).or_default();
+        *index += 1;
+        let key = match *index {
+            1 => key,
+            index => format!("{key} #{index}"),
+        };
+        entries.insert(key, value.trim());
+    }
+    Ok(entries)
+}
+
+fn annotation(key: &str) -> Option<&'static str> {
+    let key = key.split_once(" #").map_or(key, |(key, _)| key);
+    BOOT_KEYS
+        .iter()
+        .find(|(known, _)| match known.strip_suffix('-') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == *known,
+        })
+        .map(|(_, annotation)| *annotation)
+}
+
+/// Reports added, removed and changed keys sorted by key, or fails if a file isn't made of `key=value` lines.
+pub fn diff_key_values(data: OldNew<&[u8]>) -> Result<String> {
+    let text = data.try_map(str::from_utf8).context("not UTF-8")?;
+    let entries = text.try_map(parse)?;
+    let entries = entries.as_ref();
+
+    let mut out = String::new();
+    let mut write_key = |marker: char, key: &str, value: String| -> std::fmt::Result {
+        write!(out, "{marker} {key}{value}")?;
+        if let Some(annotation) = annotation(key) {
+            write!(out, "  # {annotation}")?;
+        }
+        writeln!(out)
+    };
+
+    let keys: BTreeMap<&str, OldNew<Option<&str>>> = entries
+        .old
+        .keys()
+        .chain(entries.new.keys())
+        .map(|key| {
+            let value = entries.map(|entries| entries.get(key).copied());
+            (key.as_str(), value)
+        })
+        .collect();
+    for (key, value) in keys {
+        match (value.old, value.new) {
+            (Some(old), Some(new)) if old != new => {
+                write_key('~', key, format!(" {old} -> {new}"))?
+            }
+            (Some(old), None) => write_key('-', key, format!("={old}"))?,
+            (None, Some(new)) => write_key('+', key, format!("={new}"))?,
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_config() {
+        let text = "gfx-enable-gfx-jobs=1\r\n# comment\n\nscripting-runtime-version = latest\nwait-for-native-debugger=0\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            [
+                ("gfx-enable-gfx-jobs".to_owned(), "1"),
+                ("scripting-runtime-version".to_owned(), "latest"),
+                ("wait-for-native-debugger".to_owned(), "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sections() {
+        let text = "top=1\n; comment\n[Audio]\nvolume=0.5\n[Video]\nvsync=on=off\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(entries["top"], "1");
+        assert_eq!(entries["Audio.volume"], "0.5");
+        // only the first `=` separates the value
+        assert_eq!(entries["Video.vsync"], "on=off");
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    fn duplicates() {
+        let old = "job-worker-count=4\njob-worker-count=8\n[A]\nx=1\n[B]\nx=1\n";
+        let new = "job-worker-count=4\njob-worker-count=2\n[A]\nx=1\n[B]\nx=1\n";
+        let entries = parse(old).unwrap();
+        assert_eq!(entries["job-worker-count"], "4");
+        assert_eq!(entries["job-worker-count #2"], "8");
+        // the same key in different sections isn't repeated
+        assert_eq!(entries.len(), 4);
+
+        let diff = diff_key_values(OldNew::new(old.as_bytes(), new.as_bytes())).unwrap();
+        assert_eq!(
+            diff,
+            "~ job-worker-count #2 8 -> 2  # job system worker threads\n"
+        );
+    }
+
+    #[test]
+    fn not_key_value() {
+        assert!(parse("key=value\nsome text\n").is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let old =
+            b"gfx-enable-gfx-jobs=1\nmemorysetup-bucket-allocator-granularity=16\nheadless=0\n";
+        let new =
+            b"gfx-enable-gfx-jobs=0\nmemorysetup-bucket-allocator-granularity=16\nvr-enabled=1\n";
+        let diff = diff_key_values(OldNew::new(&old[..], &new[..])).unwrap();
+        assert_eq!(
+            diff,
+            "~ gfx-enable-gfx-jobs 1 -> 0  # graphics jobs\n\
+             - headless=0  # runs without graphics\n\
+             + vr-enabled=1  # VR support\n"
+        );
+    }
+}
@@ -1,9 +1,13 @@
+pub mod addressables;
 pub mod cs;
+mod key_value;
 pub mod unity;
+mod values;
 
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 
-use anstream::eprintln;
 use anyhow::{Context as _, Result};
 use diffy::{DiffOptions, PatchFormatter};
 use json_diff_ng::DiffType;
@@ -12,11 +16,14 @@ use rabex_env::game_files::GameFiles;
 use rabex_env::rabex::tpk::TpkTypeTreeBlob;
 use rabex_env::rabex::typetree::typetree_cache::sync::TypeTreeCache;
 use regex::Regex;
+use rustc_hash::FxHasher;
 
 use crate::old_new::OldNew;
 
 pub struct Context<'a> {
     pub file_filter: String,
+    /// Directories the diffed paths are relative to, for data stored next to a file like `.resS`
+    pub root_dir: OldNew<&'a Path>,
 
     pub text_diff_context_size: usize,
 
@@ -24,17 +31,148 @@ pub struct Context<'a> {
     /// Ignore new values of `0`, `[]`, etc.
     pub json_ignore_new_default: bool,
     pub json_sort: bool,
+    /// Ignore numeric changes within the tolerance
+    pub json_float_tolerance: Option<FloatTolerance>,
+    /// Match array elements by an identity field instead of their position
+    pub json_array_keys: Option<ArrayKeys>,
 
     pub cs_decompile_assembly: bool,
 
     pub unity_game: Option<OldNew<Environment<GameFiles, &'a TypeTreeCache<TpkTypeTreeBlob>>>>,
     pub unity_filter: unity::Filter,
+    /// Diff the typetrees embedded in serialized files
+    pub unity_schema_diff: bool,
+    /// Dump the components of added and removed objects
+    pub unity_dump_objects: Option<unity::DumpLimits>,
+    /// Write changed, added and removed audio clips next to the diff, as WAV, Ogg or FSB5
+    pub unity_extract_audio: bool,
+    /// Write SVG overlays of the old and new shape of changed 2D colliders next to the diff
+    pub unity_collider_svg: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub absolute: f64,
+    /// Relative to the larger magnitude of the old and new value
+    pub relative: f64,
+}
+impl Tolerance {
+    fn accepts(&self, old: f64, new: f64) -> bool {
+        let delta = (new - old).abs();
+        delta <= self.absolute || delta <= self.relative * old.abs().max(new.abs())
+    }
+}
+
+pub struct FloatTolerance {
+    pub default: Tolerance,
+    /// Matched against `{class}{path}`, e.g. `Transform.m_LocalPosition.x`. The first match wins.
+    pub overrides: Vec<(Regex, Tolerance)>,
+}
+impl FloatTolerance {
+    fn get(&self, path: &str) -> &Tolerance {
+        self.overrides
+            .iter()
+            .find(|(regex, _)| regex.is_match(path))
+            .map_or(&self.default, |(_, tolerance)| tolerance)
+    }
+
+    fn hash(&self, hasher: &mut impl Hasher) {
+        let mut hash_tolerance = |tolerance: &Tolerance| {
+            tolerance.absolute.to_bits().hash(hasher);
+            tolerance.relative.to_bits().hash(hasher);
+        };
+        hash_tolerance(&self.default);
+        for (regex, tolerance) in &self.overrides {
+            regex.as_str().hash(hasher);
+            hash_tolerance(tolerance);
+        }
+    }
+}
+
+pub struct ArrayKeys {
+    /// Fields tried in order, the first one present and unique in every element of both versions wins.
+    /// Nested fields are separated by `.`, e.g. `component.$target`.
+    pub default: Vec<String>,
+    /// Matched against `{class}{path}` of the array, e.g. `MonoBehaviour.items`. The first match wins,
+    /// an empty key keeps matching by position.
+    pub overrides: Vec<(Regex, String)>,
+}
+impl ArrayKeys {
+    fn get(&self, path: &str) -> &[String] {
+        self.overrides
+            .iter()
+            .find(|(regex, _)| regex.is_match(path))
+            .map_or(self.default.as_slice(), |(_, key)| {
+                std::slice::from_ref(key)
+            })
+    }
+
+    fn hash(&self, hasher: &mut impl Hasher) {
+        self.default.hash(hasher);
+        for (regex, key) in &self.overrides {
+            regex.as_str().hash(hasher);
+            key.hash(hasher);
+        }
+    }
+}
+
+impl Context<'_> {
+    /// Identifies the configuration that affects the output of `differ`, so that results can be reused across runs.
+    pub fn fingerprint(&self, differ: Differ) -> String {
+        let mut hasher = FxHasher::default();
+        differ.name().hash(&mut hasher);
+        differ.version().hash(&mut hasher);
+        self.text_diff_context_size.hash(&mut hasher);
+
+        if matches!(
+            differ,
+            Differ::Json | Differ::SerializedFile | Differ::BundleFile
+        ) {
+            self.json_ignore_regex
+                .as_ref()
+                .map(Regex::as_str)
+                .hash(&mut hasher);
+            self.json_ignore_new_default.hash(&mut hasher);
+            self.json_sort.hash(&mut hasher);
+            if let Some(tolerance) = &self.json_float_tolerance {
+                tolerance.hash(&mut hasher);
+            }
+            if let Some(keys) = &self.json_array_keys {
+                keys.hash(&mut hasher);
+            }
+        }
+        if differ == Differ::Assembly {
+            self.cs_decompile_assembly.hash(&mut hasher);
+        }
+        if matches!(differ, Differ::SerializedFile | Differ::BundleFile) {
+            let mut ignore_classes: Vec<_> = self
+                .unity_filter
+                .ignore_classes
+                .iter()
+                .map(|class_id| format!("{class_id:?}"))
+                .collect();
+            ignore_classes.sort();
+            ignore_classes.hash(&mut hasher);
+            self.unity_schema_diff.hash(&mut hasher);
+            self.unity_dump_objects.hash(&mut hasher);
+            self.unity_extract_audio.hash(&mut hasher);
+            self.unity_collider_svg.hash(&mut hasher);
+        }
+
+        format!("{:016x}", hasher.finish())
+    }
 }
 
 pub struct DiffResult {
     pub content: String,
     pub extension: Option<&'static str>,
     pub children: Vec<(PathBuf, DiffResult)>,
+    /// Binary outputs like images, placed next to the children
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+    /// Problems that didn't stop the diff, like skipped objects
+    pub warnings: Vec<String>,
+    /// Reported once for the whole run
+    pub script_fields: unity::ScriptFieldChanges,
 }
 impl DiffResult {
     pub fn new_with_ext(content: String, extension: &'static str) -> Self {
@@ -42,6 +180,9 @@ impl DiffResult {
             content,
             extension: Some(extension),
             children: Vec::new(),
+            files: Vec::new(),
+            warnings: Vec::new(),
+            script_fields: Default::default(),
         }
     }
     pub fn diff_ext(content: String) -> Self {
@@ -51,6 +192,18 @@ impl DiffResult {
         self.children = children;
         self
     }
+    pub fn with_files(mut self, files: Vec<(PathBuf, Vec<u8>)>) -> Self {
+        self.files = files;
+        self
+    }
+    pub fn with_warnings(mut self, warnings: Vec<String>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+    pub fn with_script_fields(mut self, script_fields: unity::ScriptFieldChanges) -> Self {
+        self.script_fields = script_fields;
+        self
+    }
 }
 impl From<String> for DiffResult {
     fn from(content: String) -> Self {
@@ -58,72 +211,129 @@ impl From<String> for DiffResult {
             content,
             extension: None,
             children: Vec::new(),
+            files: Vec::new(),
+            warnings: Vec::new(),
+            script_fields: Default::default(),
         }
     }
 }
 
-pub fn diff(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<DiffResult> {
-    let file_name = path
-        .file_name()
-        .context("file has no filename")?
-        .to_str()
-        .context("non-utf8 filename")?;
-    let extension = path
-        .extension()
-        .map(|e| e.to_str().context("non-utf8 extension"))
-        .transpose()?;
-
-    if extension == Some("dll") && cx.cs_decompile_assembly {
-        let diff = cs::diff_assembly(cx, data)?;
-        return Ok(diff);
-    }
-
-    if extension == Some("json") {
-        return Ok(DiffResult::diff_ext(diff_json(
-            cx,
-            data.try_map(serde_json::from_slice::<serde_json::Value>)?
-                .as_ref(),
-        )?));
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Differ {
+    Assembly,
+    Json,
+    SerializedFile,
+    BundleFile,
+    AddressablesCatalog,
+    KeyValue,
+    Text,
+}
+impl Differ {
+    pub fn detect(cx: &Context, path: &Path) -> Result<Differ> {
+        let file_name = path
+            .file_name()
+            .context("file has no filename")?
+            .to_str()
+            .context("non-utf8 filename")?;
+        let extension = path
+            .extension()
+            .map(|e| e.to_str().context("non-utf8 extension"))
+            .transpose()?;
 
-    if extension == Some("assets")
-        || ["globalgamemanagers", "unity_default_resources"].contains(&file_name)
-        || file_name
-            .strip_prefix("level")
-            .is_some_and(|i| i.parse::<usize>().is_ok())
-    {
-        return unity::diff_serializedfile(cx, path, data)
-            .map(DiffResult::diff_ext)
-            .context("failed to diff unity serializedfile");
+        let differ = if extension == Some("dll") && cx.cs_decompile_assembly {
+            Differ::Assembly
+        } else if extension == Some("json") {
+            Differ::Json
+        } else if extension == Some("assets")
+            || ["globalgamemanagers", "unity_default_resources"].contains(&file_name)
+            || file_name
+                .strip_prefix("level")
+                .is_some_and(|i| i.parse::<usize>().is_ok())
+        {
+            Differ::SerializedFile
+        } else if extension == Some("bundle") {
+            Differ::BundleFile
+        } else if file_name == "catalog.bin" {
+            Differ::AddressablesCatalog
+        } else if file_name == "boot.config" || extension == Some("ini") {
+            Differ::KeyValue
+        } else {
+            Differ::Text
+        };
+        Ok(differ)
     }
 
-    if extension == Some("bundle") {
-        return unity::diff_bundlefile(cx, path, data)
-            .map(DiffResult::diff_ext)
-            .context("failed to diff unity bundlefile");
+    /// Bump when the output of a differ changes, so that results of previous versions aren't reused.
+    /// The JSON diff is part of the serialized file and bundle differs, changing it bumps them as well.
+    pub fn version(self) -> u32 {
+        match self {
+            Differ::Assembly => 1,
+            Differ::Json => 1,
+            Differ::SerializedFile => 1,
+            Differ::BundleFile => 1,
+            Differ::AddressablesCatalog => 1,
+            Differ::KeyValue => 1,
+            Differ::Text => 1,
+        }
     }
 
-    if let Some(content) = try_diff_text(cx, data) {
-        return Ok(DiffResult::diff_ext(content));
+    pub fn name(self) -> &'static str {
+        match self {
+            Differ::Assembly => "assembly",
+            Differ::Json => "json",
+            Differ::SerializedFile => "serializedfile",
+            Differ::BundleFile => "bundlefile",
+            Differ::AddressablesCatalog => "addressables",
+            Differ::KeyValue => "keyvalue",
+            Differ::Text => "text",
+        }
     }
+}
 
-    let style = warn_style();
-    eprintln!(
-        "{style}Unrecognized binary format: {}{style:#}",
-        path.display()
-    );
+pub fn diff(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<DiffResult> {
+    match Differ::detect(cx, path)? {
+        Differ::Assembly => cs::diff_assembly(cx, data),
+        Differ::Json => Ok(DiffResult::diff_ext(diff_json(
+            cx,
+            data.try_map(serde_json::from_slice::<serde_json::Value>)?
+                .as_ref(),
+            "",
+        )?)),
+        Differ::SerializedFile => unity::diff_serializedfile(cx, path, data)
+            .context("failed to diff unity serializedfile"),
+        Differ::BundleFile => {
+            unity::diff_bundlefile(cx, path, data).context("failed to diff unity bundlefile")
+        }
+        Differ::AddressablesCatalog => addressables::diff_catalog(data)
+            .map(DiffResult::diff_ext)
+            .context("failed to diff addressables catalog"),
+        Differ::KeyValue => {
+            // files that only look like `key=value` are diffed line by line
+            let content = key_value::diff_key_values(data)
+                .ok()
+                .or_else(|| try_diff_text(cx, data))
+                .context("not a text file")?;
+            Ok(DiffResult::diff_ext(content))
+        }
+        Differ::Text => {
+            if let Some(content) = try_diff_text(cx, data) {
+                return Ok(DiffResult::diff_ext(content));
+            }
 
-    Ok(DiffResult {
-        content: "binary file differs".into(),
-        extension: None,
-        children: Vec::new(),
-    })
+            Ok(DiffResult::from("binary file differs".to_owned())
+                .with_warnings(vec!["Unrecognized binary format".to_owned()]))
+        }
+    }
 }
 
-fn warn_style() -> anstyle::Style {
+pub fn warn_style() -> anstyle::Style {
     anstyle::Style::new().fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Yellow)))
 }
 
+pub fn error_style() -> anstyle::Style {
+    anstyle::Style::new().fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Red)))
+}
+
 fn try_diff_text(cx: &Context, data: OldNew<&[u8]>) -> Option<String> {
     data.try_map(str::from_utf8)
         .ok()
@@ -155,9 +365,31 @@ fn diff_text_context(data: OldNew<&str>, context_len: usize) -> String {
     }
 }
 
-fn diff_json(cx: &Context, data: OldNew<&serde_json::Value>) -> Result<String> {
+/// `class` is prefixed to the paths matched by [`Context::json_float_tolerance`] and [`Context::json_array_keys`].
+/// It is the class name of the object, e.g. `MonoBehaviour` for every script. Parts of an object diffed on their own
+/// append the fields leading to them, e.g. `QualitySettings.m_QualitySettings`.
+fn diff_json(cx: &Context, data: OldNew<&serde_json::Value>, class: &str) -> Result<String> {
     use std::fmt::Write;
 
+    let keyed;
+    let mut reordered = Vec::new();
+    let data = match &cx.json_array_keys {
+        Some(keys) => {
+            let mut data = data.map(serde_json::Value::clone);
+            let mut path = class.to_owned();
+            key_arrays(
+                keys,
+                &mut path,
+                &mut data.old,
+                &mut data.new,
+                &mut reordered,
+            );
+            keyed = data;
+            keyed.as_ref()
+        }
+        None => data,
+    };
+
     let diffs = json_diff_ng::compare_serde_values(
         data.old,
         data.new,
@@ -166,38 +398,34 @@ fn diff_json(cx: &Context, data: OldNew<&serde_json::Value>) -> Result<String> {
     )?;
     let mut f = String::new();
 
-    let all_diffs = diffs.all_diffs();
-    let all_mismatch = all_diffs
-        .iter()
-        .all(|(diff_type, _)| matches!(diff_type, DiffType::Mismatch));
-    for (diff_type, diff_path) in all_diffs {
+    let mut all_diffs = diffs.all_diffs();
+    all_diffs.retain(|(diff_type, diff_path)| {
         if cx.json_ignore_new_default
             && let DiffType::RightExtra = diff_type
+            && let Some(new_value) = diff_path.resolve(data.new)
+            && is_json_default(new_value)
         {
-            match diff_path.resolve(data.new) {
-                Some(new_value) => {
-                    if is_json_default(new_value) {
-                        continue;
-                    }
-                }
-                None => {
-                    /*let style = warn_style();
-                    eprintln!(
-                        "{style}Couldn't look up path '{}' {style:#}",
-                        diff_path
-                            .path
-                            .iter()
-                            .map(|x| format!(".{x}"))
-                            .collect::<String>()
-                    );*/
-                }
-            }
+            return false;
         }
 
-        if !f.is_empty() {
-            f.push('\n');
+        if let Some(tolerance) = &cx.json_float_tolerance
+            && let DiffType::Mismatch = diff_type
+            && let Some(number) = floats(data.map(|data| diff_path.resolve(data)))
+        {
+            let path: String = diff_path.path.iter().map(|x| format!(".{x}")).collect();
+            return !tolerance
+                .get(&format!("{class}{path}"))
+                .accepts(number.old, number.new);
         }
 
+        true
+    });
+
+    let all_mismatch = all_diffs
+        .iter()
+        .all(|(diff_type, _)| matches!(diff_type, DiffType::Mismatch));
+    let mut collapsed = HashSet::new();
+    for (diff_type, diff_path) in all_diffs {
         let diff_type_msg = match diff_type {
             DiffType::RootMismatch => "Mismatch at root.",
             DiffType::LeftExtra => "< ",
@@ -205,14 +433,41 @@ fn diff_json(cx: &Context, data: OldNew<&serde_json::Value>) -> Result<String> {
             DiffType::Mismatch if all_mismatch => "",
             DiffType::Mismatch => "  ",
         };
+
+        if let DiffType::Mismatch = diff_type
+            && let Some((len, value)) = values::collapse(&diff_path.path, data)
+            // e.g. curve tangents aren't part of the rendering
+            && value.changed()
+        {
+            let path: String = diff_path.path[..len]
+                .iter()
+                .map(|x| format!(".{x}"))
+                .collect();
+            if collapsed.insert(path.clone()) {
+                if !f.is_empty() {
+                    f.push('\n');
+                }
+                write!(f, "{diff_type_msg}{path} {} -> {}", value.old, value.new)?;
+            }
+            continue;
+        }
+
+        if !f.is_empty() {
+            f.push('\n');
+        }
         write!(&mut f, "{}", diff_type_msg)?;
 
         for element in &diff_path.path {
             write!(&mut f, ".{element}")?;
         }
-        if let Some((left, right)) = diff_path.values {
+        if let Some((left, right)) = &diff_path.values {
             if left != right {
                 write!(f, " {left} -> {right}")?;
+                if cx.json_float_tolerance.is_some()
+                    && let Some(number) = floats(data.map(|data| diff_path.resolve(data)))
+                {
+                    write_number_delta(&mut f, number)?;
+                }
             } else {
                 write!(f, " {left}")?;
             }
@@ -228,10 +483,113 @@ fn diff_json(cx: &Context, data: OldNew<&serde_json::Value>) -> Result<String> {
         }
     }
 
+    for path in reordered {
+        if !f.is_empty() {
+            f.push('\n');
+        }
+        let diff_type_msg = if all_mismatch { "" } else { "  " };
+        write!(f, "{diff_type_msg}{} order changed", &path[class.len()..])?;
+    }
+
     Ok(f)
     // .consume(|data| format!("old: {}\nnew: {}", data.old, data.new)))
 }
 
+/// Turns arrays whose elements have an identity key into objects like `{"[m_Name=\"Sword\"]": {..}}`,
+/// so that inserting an element shows up as one addition instead of every following index changing.
+///
+/// Arrays without a usable key are left alone, including the arrays nested in their elements,
+/// because the positional matching may pair up elements differently.
+/// Objects don't keep their order, so the paths of keyed arrays whose common elements were reordered are collected.
+fn key_arrays(
+    keys: &ArrayKeys,
+    path: &mut String,
+    old: &mut serde_json::Value,
+    new: &mut serde_json::Value,
+    reordered: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    if let (Value::Array(old_items), Value::Array(new_items)) = (&mut *old, &mut *new) {
+        let Some((key, ids)) = keys.get(path).iter().find_map(|key| {
+            let ids = OldNew::new(&*old_items, &*new_items)
+                .try_map(|items| element_ids(items, key).ok_or(()))
+                .ok()?;
+            Some((key, ids))
+        }) else {
+            return;
+        };
+        let common = |ids: &[String], others: &[String]| {
+            let others: HashSet<_> = others.iter().collect();
+            ids.iter()
+                .filter(|id| others.contains(id))
+                .collect::<Vec<_>>()
+        };
+        if common(&ids.old, &ids.new) != common(&ids.new, &ids.old) {
+            reordered.push(path.clone());
+        }
+        let keyed = |items: Vec<Value>, ids: Vec<String>| {
+            let entries = ids.into_iter().map(|id| format!("[{key}={id}]"));
+            Value::Object(entries.zip(items).collect())
+        };
+        let (old_items, new_items) = (std::mem::take(old_items), std::mem::take(new_items));
+        *old = keyed(old_items, ids.old);
+        *new = keyed(new_items, ids.new);
+    }
+
+    if let (Value::Object(old), Value::Object(new)) = (old, new) {
+        let len = path.len();
+        for (name, old) in old.iter_mut() {
+            if let Some(new) = new.get_mut(name) {
+                path.push('.');
+                path.push_str(name);
+                key_arrays(keys, path, old, new, reordered);
+                path.truncate(len);
+            }
+        }
+    }
+}
+
+/// The rendered identity of each element, if every element has one and they are all distinct
+fn element_ids(items: &[serde_json::Value], key: &str) -> Option<Vec<String>> {
+    let mut seen = HashSet::new();
+    items
+        .iter()
+        .map(|item| {
+            let id = key
+                .split('.')
+                .try_fold(item, |value, field| value.get(field))?;
+            let id = values::render_any(id);
+            seen.insert(id.clone()).then_some(id)
+        })
+        .collect()
+}
+
+/// Both values, if both are floats. Integers like path IDs and hashes are compared exactly,
+/// going through `f64` would merge distinct values above 2^53.
+fn floats(value: OldNew<Option<&serde_json::Value>>) -> Option<OldNew<f64>> {
+    let number = value
+        .try_map(|value| match value {
+            Some(serde_json::Value::Number(number)) if number.is_f64() => number.as_f64().ok_or(()),
+            _ => Err(()),
+        })
+        .ok()?;
+    (number.old.is_finite() && number.new.is_finite()).then_some(number)
+}
+
+fn write_number_delta(f: &mut String, number: OldNew<f64>) -> std::fmt::Result {
+    use std::fmt::Write;
+
+    // unity floats are single precision, printing them as such avoids noise like 0.20000000298023224
+    let delta = (number.new - number.old) as f32;
+    write!(f, " ({delta:+}")?;
+    if number.old != 0.0 {
+        let percent = (number.new - number.old) / number.old.abs() * 100.0;
+        write!(f, ", {percent:+.1}%")?;
+    }
+    write!(f, ")")
+}
+
 fn is_json_default(new_value: &serde_json::Value) -> bool {
     match new_value {
         serde_json::Value::Null => true,
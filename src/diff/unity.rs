@@ -1,10 +1,8 @@
 use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Display, Write};
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anstream::eprintln;
-use anstyle::{Color, Style};
 use anyhow::{Context as _, Result};
 use indexmap::IndexMap;
 use rabex::files::bundlefile::{BundleFileReader, ExtractionConfig};
@@ -14,15 +12,36 @@ use rabex::serde_typetree;
 use rabex::typetree::TypeTreeProvider;
 use rabex_env::game_files::GameFiles;
 use rabex_env::handle::{ObjectRefHandle, SerializedFileHandle};
+use rabex_env::rabex::UnityVersion;
 use rabex_env::rabex::files::SerializedFile;
 use rabex_env::resolver::BasedirEnvResolver;
 use rabex_env::unity::types::{GameObject, MonoBehaviour, Transform};
-use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hash::FxHashSet;
 use serde::Deserialize;
 
 use crate::old_new::OldNew;
 
-use super::Context;
+use super::{Context, DiffResult};
+
+mod animation;
+mod asset_bundle;
+mod assets;
+mod audio;
+mod geometry;
+mod localization;
+mod matching;
+mod material;
+mod playmaker;
+mod script_fields;
+mod settings;
+mod streamed;
+mod text_asset;
+mod texture;
+mod typetree;
+
+use streamed::StreamedData;
+
+pub use script_fields::ScriptFieldChanges;
 
 pub struct Filter {
     pub ignore_classes: HashSet<ClassId>,
@@ -33,8 +52,21 @@ impl Filter {
     }
 }
 
-pub fn diff_serializedfile(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<String> {
-    diff_serializedfile_smart(cx, path, data)
+/// Limits for dumping the components of added and removed objects.
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct DumpLimits {
+    /// How many levels of children to include
+    pub max_depth: usize,
+    /// Maximum size in bytes of the JSON dumped per object, including its children
+    pub max_size: usize,
+}
+
+pub fn diff_serializedfile(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<DiffResult> {
+    let streamed = cx.root_dir.map(|root_dir| {
+        let path = root_dir.join(path);
+        StreamedData::Directory(path.parent().unwrap_or(root_dir).to_owned())
+    });
+    diff_serializedfile_smart(cx, path, data, streamed.as_ref())
 }
 
 fn write_object_hierarchy<W: std::fmt::Write, R: BasedirEnvResolver, P: TypeTreeProvider>(
@@ -72,7 +104,12 @@ fn write_object_hierarchy<W: std::fmt::Write, R: BasedirEnvResolver, P: TypeTree
     Ok(())
 }
 
-fn diff_serializedfile_smart(cx: &Context, _: &Path, data: OldNew<&[u8]>) -> Result<String> {
+fn diff_serializedfile_smart(
+    cx: &Context,
+    _: &Path,
+    data: OldNew<&[u8]>,
+    streamed: OldNew<&StreamedData>,
+) -> Result<DiffResult> {
     let env = cx
         .unity_game
         .as_ref()
@@ -101,6 +138,10 @@ fn diff_serializedfile_smart(cx: &Context, _: &Path, data: OldNew<&[u8]>) -> Res
         text.push('\n');
     }
 
+    if cx.unity_schema_diff {
+        text.push_str(&typetree::diff_types(file.as_ref())?);
+    }
+
     /*if path.extension().is_some_and(|x| x == "sharedAssets") {
         return Ok(text);
     }*/
@@ -133,26 +174,55 @@ fn diff_serializedfile_smart(cx: &Context, _: &Path, data: OldNew<&[u8]>) -> Res
         current_path: Vec::new(),
         current_old: PathId::default(),
         old_seen: HashSet::default(),
+        added: Vec::new(),
         out: &mut text,
+        files: Vec::new(),
+        script_fields: ScriptFieldChanges::default(),
     };
     cx.visit_roots()?;
+    cx.visit_moved()?;
 
     for (path_id, (t, go)) in &cx.transforms.old {
         if !cx.old_seen.contains(path_id) {
-            let mut components: Vec<_> = std::iter::successors(Some((t, go)), |(t, _)| {
-                let parent = cx.transforms.old.get(&t.m_Father.m_PathID)?;
-                Some((&parent.0, &parent.1))
-            })
-            .map(|(_, go)| go.m_Name.as_str())
-            .collect();
-            components.reverse();
-            let path = components.join("/");
-
+            let path = object_path(&cx.transforms.old, *path_id);
             writeln!(&mut cx.out, "--- Removed object '{}' ---", path)?;
+
+            // children of removed objects are included in the parent's dump
+            let topmost = t.m_Father.is_null() || cx.old_seen.contains(&t.m_Father.m_PathID);
+            if let Some(limits) = cx.cx.unity_dump_objects
+                && topmost
+            {
+                let mut budget = limits.max_size;
+                dump_object(
+                    cx.out,
+                    cx.cx,
+                    &cx.file.old,
+                    (t, go),
+                    0,
+                    limits.max_depth,
+                    &mut budget,
+                )?;
+            }
         }
     }
 
-    Ok(text)
+    let mut files = std::mem::take(&mut cx.files);
+    let mut script_fields = std::mem::take(&mut cx.script_fields);
+    let mut warnings = Vec::new();
+    assets::diff_assets(
+        cx.cx,
+        cx.file.as_ref(),
+        streamed,
+        cx.out,
+        &mut files,
+        &mut warnings,
+        &mut script_fields,
+    )?;
+
+    Ok(DiffResult::diff_ext(text)
+        .with_files(files)
+        .with_warnings(warnings)
+        .with_script_fields(script_fields))
 }
 
 struct SceneMatcher<'a, P> {
@@ -164,12 +234,30 @@ struct SceneMatcher<'a, P> {
     current_path: Vec<String>,
 
     out: &'a mut String,
+    /// Binary outputs like collider overlays
+    files: Vec<(PathBuf, Vec<u8>)>,
+    script_fields: ScriptFieldChanges,
 
     old_seen: FxHashSet<PathId>,
+    /// New objects without a counterpart under the same parent, together with their parent's path.
+    /// They are matched against the unseen old objects in [`SceneMatcher::visit_moved`].
+    added: Vec<(PathId, Vec<String>)>,
 }
 impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
-    fn added_object(&mut self, path: String) -> Result<()> {
+    fn added_object(&mut self, path: String, object: (&Transform, &GameObject)) -> Result<()> {
         writeln!(self.out, "--- Added Object '{}' ---", path)?;
+        if let Some(limits) = self.cx.unity_dump_objects {
+            let mut budget = limits.max_size;
+            dump_object(
+                self.out,
+                self.cx,
+                &self.file.new,
+                object,
+                0,
+                limits.max_depth,
+                &mut budget,
+            )?;
+        }
         Ok(())
     }
     fn compare(&mut self, path: String, data: OldNew<(&Transform, &GameObject)>) -> Result<()> {
@@ -180,20 +268,7 @@ impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
                     let mut components = BTreeMap::new();
                     for component in go.components(file.file, &file.env.tpk) {
                         let component = ObjectRefHandle::new(component?, file.reborrow());
-
-                        let component_key = match component.class_id() {
-                            ClassId::MonoBehaviour => {
-                                match component.cast::<MonoBehaviour>().mono_script()? {
-                                    Some(script) => {
-                                        ComponentKey::Script(script.full_name().into_owned())
-                                    }
-                                    None => ComponentKey::ClassId(ClassId::MonoBehaviour),
-                                }
-                            }
-                            class_id => ComponentKey::ClassId(class_id),
-                        };
-
-                        components.insert(component_key, component);
+                        components.insert(component_key(&component)?, component);
                     }
                     Ok(components)
                 })?;
@@ -216,10 +291,7 @@ impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
                 continue;
             }
 
-            let data = comp.map(|comp| {
-                let start = comp.object.info.m_Offset as usize;
-                &comp.file.data[start..start + comp.object.info.m_Size as usize]
-            });
+            let data = comp.map(raw_data);
             if data.changed() {
                 let mut value = data.try_map_zip(&comp, |data, comp| {
                     serde_typetree::from_reader_endianed::<serde_json::Value>(
@@ -229,10 +301,33 @@ impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
                     )
                 })?;
 
+                if let ComponentKey::Script(script) = component {
+                    self.script_fields.record(
+                        script,
+                        comp.map(|comp| &*comp.object.tt),
+                        &mut value,
+                    );
+                }
+
                 qualify_pptrs(&self.file.old, &mut value.old).context("qualifying pptrs")?;
                 qualify_pptrs(&self.file.new, &mut value.new).context("qualifying pptrs")?;
 
-                let diff = super::diff_json(self.cx, value.as_ref())?;
+                let diff = match component {
+                    ComponentKey::ClassId(class_id) if geometry::is_collider(*class_id) => {
+                        // sibling GameObjects can share a path
+                        let name = format!("{}.{}", sanitize_file_name(&path), comp.new.path_id());
+                        geometry::diff_collider(self.cx, *class_id, value, &name, &mut self.files)?
+                    }
+                    _ if component.class_id() == ClassId::MonoBehaviour
+                        && playmaker::is_fsm(value.as_ref()) =>
+                    {
+                        playmaker::diff(self.cx, value, "MonoBehaviour")?
+                    }
+                    _ => {
+                        let class = format!("{:?}", component.class_id());
+                        crate::diff::diff_json(self.cx, value.as_ref(), &class)?
+                    }
+                };
                 if !diff.is_empty() {
                     writeln!(self.out, "--- Changed {} @ '{}' ---", component, path)?;
                     writeln!(self.out, "{}", diff)?;
@@ -244,7 +339,6 @@ impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
     }
 
     fn visit(&mut self, transform: &Transform, go: &GameObject) -> Result<()> {
-        assert!(!go.m_Name.is_empty());
         self.current_path.push(go.m_Name.clone());
 
         let current_old = self.current_old;
@@ -257,290 +351,294 @@ impl<'a, P: TypeTreeProvider> SceneMatcher<'a, P> {
             OldNew::new((&old.0, &old.1), (transform, go)),
         )?;
 
-        let mut current_siblings_seen = FxHashMap::<String, usize>::default();
-        for &child in &transform.m_Children {
-            let child = self.file.new.deref(child)?;
-            let child = child.read()?;
-            let child_go = self.file.new.deref(child.m_GameObject)?.read()?;
-
-            let sibling_index = {
-                let entry = current_siblings_seen
-                    .entry(child_go.m_Name.clone())
-                    .or_default();
-                let index = *entry;
-                *entry += 1;
-                index
-            };
+        let old_children: Vec<_> = old
+            .0
+            .m_Children
+            .iter()
+            .map(|&old_child| {
+                assert!(old_child.is_local());
+                old_child.m_PathID
+            })
+            .filter(|path_id| !self.old_seen.contains(path_id))
+            .collect();
+        let new_children: Vec<_> = transform
+            .m_Children
+            .iter()
+            .map(|child| child.m_PathID)
+            .collect();
+        self.visit_children(OldNew::new(&old_children[..], &new_children[..]))?;
 
-            let old_child = old
-                .0
-                .m_Children
-                .iter()
-                .filter_map(|&old_child| {
-                    assert!(old_child.is_local());
-                    let path_id = old_child.m_PathID;
-                    let old_child = &self.transforms.old[&path_id];
-                    (old_child.1.m_Name == child_go.m_Name).then_some((
-                        path_id,
-                        &old_child.0,
-                        &old_child.1,
-                    ))
-                })
-                .nth(sibling_index);
-
-            let old_child = match old_child {
-                Some(val) => val,
-                None => {
-                    let mut added_path = self.current_path.join("/");
-                    added_path.push('/');
-                    added_path.push_str(&child_go.m_Name);
-                    self.added_object(added_path)?;
-                    continue;
-                }
+        self.current_old = current_old;
+        self.current_path.pop();
+
+        Ok(())
+    }
+
+    /// Matches old and new siblings by name, components and path ID and visits the pairs.
+    fn visit_children(&mut self, children: OldNew<&[PathId]>) -> Result<()> {
+        let transforms = self.transforms;
+        let signatures = OldNew::new(
+            signatures(&self.file.old, &transforms.old, children.old)?,
+            signatures(&self.file.new, &transforms.new, children.new)?,
+        );
+        let matches = matching::match_siblings(&signatures.old, &signatures.new);
+
+        let current_old = self.current_old;
+        for (&path_id, old_index) in children.new.iter().zip(matches) {
+            let (child, child_go) = &transforms.new[&path_id];
+
+            let Some(old_index) = old_index else {
+                self.added.push((path_id, self.current_path.clone()));
+                continue;
             };
+            let old_path_id = children.old[old_index];
+
+            let old_name = &transforms.old[&old_path_id].1.m_Name;
+            if *old_name != child_go.m_Name {
+                let parent: String = self.current_path.iter().map(|x| format!("{x}/")).collect();
+                writeln!(
+                    self.out,
+                    "--- Renamed '{parent}{old_name}' -> '{parent}{}' ---",
+                    child_go.m_Name
+                )?;
+            }
 
-            self.current_old = old_child.0;
-            self.visit(&child, &child_go)?;
+            self.current_old = old_path_id;
+            self.visit(child, child_go)?;
         }
-
         self.current_old = current_old;
-        self.current_path.pop();
 
         Ok(())
     }
 
     fn visit_roots(&mut self) -> Result<()> {
-        let mut roots_seen = FxHashMap::<&str, usize>::default();
-        for (&root, (_, root_go)) in self
-            .transforms
-            .new
-            .iter()
-            .filter(|(_, (t, _))| t.m_Father.is_null())
-        {
-            let root_seen_count = {
-                let entry = roots_seen.entry(&root_go.m_Name).or_default();
-                let seen = *entry;
-                *entry += 1;
-                seen
-            };
+        let roots = self.transforms.as_ref().map(|transforms| {
+            transforms
+                .iter()
+                .filter(|(_, (t, _))| t.m_Father.is_null())
+                .map(|(&path_id, _)| path_id)
+                .collect::<Vec<_>>()
+        });
+        self.visit_children(roots.as_ref().map(Vec::as_slice))
+    }
 
-            let matching_old = self
-                .transforms
+    /// Matches objects that were added under one parent to objects removed from another,
+    /// and reports everything else as added.
+    fn visit_moved(&mut self) -> Result<()> {
+        let transforms = self.transforms;
+        let mut added = std::mem::take(&mut self.added);
+
+        // the unmatched children of a moved object are matched in the next round,
+        // against the unmatched old children of its counterpart
+        loop {
+            let removed: Vec<PathId> = transforms
                 .old
                 .iter()
-                .filter(|(_, (t, go))| t.m_Father.is_null() && go.m_Name == root_go.m_Name)
-                .nth(root_seen_count);
-
-            let matching_old = match matching_old {
-                Some(val) => val,
-                None => {
-                    writeln!(
-                        &mut self.out,
-                        "--- Removed Object '{}'@{}\n",
-                        root_go.m_Name, root_seen_count
-                    )?;
+                .filter(|(path_id, (t, _))| {
+                    !self.old_seen.contains(*path_id)
+                        && (t.m_Father.is_null() || self.old_seen.contains(&t.m_Father.m_PathID))
+                })
+                .map(|(&path_id, _)| path_id)
+                .collect();
+
+            let added_ids: Vec<_> = added.iter().map(|&(path_id, _)| path_id).collect();
+            let signatures = OldNew::new(
+                signatures(&self.file.old, &transforms.old, &removed)?,
+                signatures(&self.file.new, &transforms.new, &added_ids)?,
+            );
+            let matches = matching::match_moved(&signatures.old, &signatures.new);
+            if matches.iter().all(Option::is_none) {
+                break;
+            }
+
+            let mut unmatched = Vec::new();
+            for ((path_id, parent_path), old_index) in added.into_iter().zip(matches) {
+                let Some(old_index) = old_index else {
+                    unmatched.push((path_id, parent_path));
                     continue;
-                }
-            };
-            self.current_old = *matching_old.0;
+                };
+                let old_path_id = removed[old_index];
+                let (child, child_go) = &transforms.new[&path_id];
+
+                let mut new_path = parent_path.clone();
+                new_path.push(child_go.m_Name.clone());
+                writeln!(
+                    self.out,
+                    "--- Moved '{}' -> '{}' ---",
+                    object_path(&transforms.old, old_path_id),
+                    new_path.join("/"),
+                )?;
+
+                self.current_path = parent_path;
+                self.current_old = old_path_id;
+                self.visit(child, child_go)?;
+            }
+            self.current_path.clear();
 
-            let root = &self.transforms.new[&root];
-            self.visit(&root.0, &root.1)?;
+            unmatched.append(&mut self.added);
+            added = unmatched;
+        }
+
+        for (path_id, mut path) in added {
+            let (child, child_go) = &transforms.new[&path_id];
+            path.push(child_go.m_Name.clone());
+            self.added_object(path.join("/"), (child, child_go))?;
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum ComponentKey {
-    Script(String),
-    ClassId(ClassId),
+fn signatures<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    transforms: &IndexMap<PathId, (Transform, GameObject)>,
+    path_ids: &[PathId],
+) -> Result<Vec<matching::Signature>> {
+    path_ids
+        .iter()
+        .map(|&path_id| {
+            let (transform, go) = transforms
+                .get(&path_id)
+                .with_context(|| format!("transform at path id {path_id} not found"))?;
+            matching::Signature::new(file, path_id, (transform, go))
+        })
+        .collect()
 }
-impl Display for ComponentKey {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ComponentKey::Script(name) => f.write_str(name),
-            ComponentKey::ClassId(class_id) => std::fmt::Debug::fmt(class_id, f),
-        }
-    }
+
+/// The `/`-separated names from the root to the object.
+fn object_path(transforms: &IndexMap<PathId, (Transform, GameObject)>, path_id: PathId) -> String {
+    let mut components: Vec<_> = std::iter::successors(transforms.get(&path_id), |(t, _)| {
+        transforms.get(&t.m_Father.m_PathID)
+    })
+    .map(|(_, go)| go.m_Name.as_str())
+    .collect();
+    components.reverse();
+    components.join("/")
 }
 
-#[allow(dead_code)]
-fn diff_serializedfile_old(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<String> {
-    let env = cx
-        .unity_game
-        .as_ref()
-        .context("cannot diff bundlefile outside unity game")?;
+/// Replaces characters that can't be part of a file name, including path separators.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
 
-    let old_reader = &mut Cursor::new(data.old);
-    let new_reader = &mut Cursor::new(data.new);
-    let mut old = SerializedFile::from_reader(old_reader)?;
-    let mut new = SerializedFile::from_reader(new_reader)?;
-    old.m_UnityVersion.get_or_insert(env.old.unity_version()?);
-    new.m_UnityVersion.get_or_insert(env.new.unity_version()?);
+fn raw_data<'a, T, P>(object: &'a ObjectRefHandle<'_, T, GameFiles, P>) -> &'a [u8] {
+    let start = object.object.info.m_Offset as usize;
+    &object.file.data[start..start + object.object.info.m_Size as usize]
+}
 
-    let old = SerializedFileHandle::new(&env.old, &old, data.old);
-    let new = SerializedFileHandle::new(&env.new, &new, data.new);
+fn component_key<T, P: TypeTreeProvider>(
+    component: &ObjectRefHandle<'_, T, GameFiles, P>,
+) -> Result<ComponentKey> {
+    Ok(match component.class_id() {
+        ClassId::MonoBehaviour => match component.cast::<MonoBehaviour>().mono_script()? {
+            Some(script) => ComponentKey::Script(script.full_name().into_owned()),
+            None => ComponentKey::ClassId(ClassId::MonoBehaviour),
+        },
+        class_id => ComponentKey::ClassId(class_id),
+    })
+}
 
-    let file = OldNew::new(old, new);
-    let file = file.as_ref();
+/// Writes the qualified JSON of every component of `object` and, up to `depth` levels, its children.
+fn dump_object<P: TypeTreeProvider>(
+    w: &mut String,
+    cx: &Context,
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    (transform, go): (&Transform, &GameObject),
+    indent: usize,
+    depth: usize,
+    budget: &mut usize,
+) -> Result<()> {
+    let prefix = "  ".repeat(indent);
+
+    for component in go.components(file.file, &file.env.tpk) {
+        let component = ObjectRefHandle::new(component?, file.reborrow());
+        let key = component_key(&component)?;
+        if !cx.unity_filter.matches(&component) {
+            writeln!(w, "{prefix}{key}")?;
+            continue;
+        }
 
-    let mut text = super::diff_text(
-        cx,
-        OldNew::new(
-            &format!("{:#?}", format::SerializedFile::from(file.old.file)),
-            &format!("{:#?}", format::SerializedFile::from(file.new.file)),
-        ),
-    );
-    if !text.is_empty() {
-        text.push('\n');
-    }
-    let object_count = file.map(|file| file.file.objects().len());
-    if object_count.changed() {
-        writeln!(
-            &mut text,
-            "{} -> {} objects",
-            object_count.old, object_count.new,
+        let mut value = serde_typetree::from_reader_endianed::<serde_json::Value>(
+            &mut Cursor::new(raw_data(&component)),
+            &component.object.tt,
+            component.file.file.m_Header.m_Endianess,
         )?;
-    }
+        qualify_pptrs(file, &mut value).context("qualifying pptrs")?;
+        let json = serde_json::to_string_pretty(&value)?;
 
-    let object_changes = file
-        .as_ref()
-        .changes(|file| file.file.objects().map(|x| x.m_PathID));
+        if json.len() > *budget {
+            writeln!(w, "{prefix}{key} (truncated, dump size limit reached)")?;
+            *budget = 0;
+            return Ok(());
+        }
+        *budget -= json.len();
 
-    if !object_changes.removed.is_empty() {
-        writeln!(
-            &mut text,
-            "Removed {} objects",
-            object_changes.removed.len()
-        )?;
-    }
-    if !object_changes.added.is_empty() {
-        writeln!(&mut text, "Added {} objects", object_changes.added.len())?;
+        writeln!(w, "{prefix}{key}")?;
+        for line in json.lines() {
+            writeln!(w, "{prefix}{line}")?;
+        }
     }
 
-    for added in object_changes.added {
-        let object = file.new.object_at::<serde_json::Value>(added)?;
-
-        if cx.unity_filter.matches(&object) {
-            let mut val = object.read()?;
-            let script = object.mono_script()?;
-            let name = name(&object, &val)?;
-
-            write!(&mut text, "--- added {:?}", object.class_id())?;
-            if let Some(script) = &script {
-                write!(&mut text, " {}", script.full_name())?;
-            }
-            write!(&mut text, " {name}")?;
-            writeln!(&mut text, " ---")?;
-
-            qualify_pptrs(file.new, &mut val)?;
-
-            writeln!(&mut text, "{}", serde_json::to_string_pretty(&val)?)?;
-        } else {
-            let val = object.read()?;
-            let name = name(&object, &val)?;
+    if depth == 0 {
+        if !transform.m_Children.is_empty() {
             writeln!(
-                &mut text,
-                "--- added {:?} {} ---",
-                object.object.info.m_ClassID, name
+                w,
+                "{prefix}({} children not shown)",
+                transform.m_Children.len()
             )?;
         }
+        return Ok(());
+    }
+    for &child in &transform.m_Children {
+        let child = file.deref(child)?.read()?;
+        let child_go = file.deref(child.m_GameObject)?.read()?;
+
+        writeln!(w, "{prefix}/{}", child_go.m_Name)?;
+        dump_object(
+            w,
+            cx,
+            file,
+            (&child, &child_go),
+            indent + 1,
+            depth - 1,
+            budget,
+        )?;
+        if *budget == 0 {
+            break;
+        }
     }
 
-    for path_id in object_changes.same {
-        let object = file
-            .as_ref()
-            .try_map(|file| file.object_at::<serde_json::Value>(path_id))?;
-        let object = object.as_ref();
-        let class_id = object.map(|val| val.class_id());
-
-        let data = object.try_map_zip(&file, |obj, file| {
-            obj.object.get_raw_data(&mut file.reader())
-        })?;
-
-        if data.changed()
-            && let Err(e) = (|| -> Result<()> {
-                let old_value = serde_typetree::from_reader_endianed::<serde_json::Value>(
-                    &mut Cursor::new(data.old),
-                    &object.old.object.tt,
-                    object.old.file.file.m_Header.m_Endianess,
-                )?;
-                let new_value = serde_typetree::from_reader_endianed::<serde_json::Value>(
-                    &mut Cursor::new(data.new),
-                    &object.new.object.tt,
-                    object.new.file.file.m_Header.m_Endianess,
-                )?;
-
-                let mut value = OldNew::new(old_value, new_value);
-
-                let name = object.try_map_zip(&value, |object, val| name(object, val))?;
-                let script = object.try_map(|obj| obj.mono_script())?;
-
-                qualify_pptrs(file.old, &mut value.old)?;
-                qualify_pptrs(file.new, &mut value.new)?;
-
-                let matches_filter = cx.unity_filter.matches(object.new);
-                let diff = matches_filter
-                    .then(|| super::diff_json(cx, value.as_ref()))
-                    .transpose()?
-                    .filter(|diff| !diff.is_empty());
-
-                if let Some(diff) = diff {
-                    let mut major_change = false;
-
-                    write!(&mut text, "--- changed {:?}", class_id.new)?;
-                    if class_id.changed() {
-                        write!(&mut text, " (previously {:?})", class_id.old)?;
-                        major_change = true;
-                    }
-                    if let Some(script_new) = &script.new {
-                        let script_new_name = script_new.full_name();
-                        write!(&mut text, " {}", script_new_name)?;
-                        if let Some(script_old) = &script.old {
-                            let script_old_name = script_old.full_name();
-                            if script_new_name != script_old_name {
-                                major_change = true;
-                                write!(&mut text, " (previously {})", script_new.full_name())?;
-                            }
-                        }
-                    }
-                    write!(&mut text, " {}", name.new)?;
-                    if name.changed() {
-                        write!(&mut text, " (previously {})", name.old)?;
-                        major_change = true;
-                    }
-                    writeln!(&mut text, " ---")?;
-
-                    if !major_change {
-                        writeln!(&mut text, "{}", diff)?;
-                    }
-                }
-
-                Ok(())
-            })()
-        {
-            writeln!(
-                &mut text,
-                "--- change object {:?} at path id {path_id} ---",
-                object.new.class_id()
-            )?;
+    Ok(())
+}
 
-            let style = Style::new().fg_color(Some(Color::Ansi(anstyle::AnsiColor::Red)));
-            eprintln!(
-                "{style}Skipping {:?} object in {} (Path ID {}): {e:?}{style:#}",
-                object.new.class_id(),
-                path.display(),
-                path_id,
-            );
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ComponentKey {
+    Script(String),
+    ClassId(ClassId),
+}
+impl ComponentKey {
+    fn class_id(&self) -> ClassId {
+        match self {
+            ComponentKey::Script(_) => ClassId::MonoBehaviour,
+            ComponentKey::ClassId(class_id) => *class_id,
+        }
+    }
+}
+impl Display for ComponentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComponentKey::Script(name) => f.write_str(name),
+            ComponentKey::ClassId(class_id) => std::fmt::Debug::fmt(class_id, f),
         }
     }
-
-    Ok(text)
 }
 
-pub fn diff_bundlefile(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<String> {
+pub fn diff_bundlefile(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result<DiffResult> {
     let env = cx
         .unity_game
         .as_ref()
@@ -552,40 +650,101 @@ pub fn diff_bundlefile(cx: &Context, path: &Path, data: OldNew<&[u8]>) -> Result
         Ok(bundle)
     })?;
     let bundle = bundle.as_ref();
+    let streamed = bundle.map(StreamedData::bundle);
 
-    let changes = bundle.changes(|bundle| bundle.files().iter().map(|file| file.path.as_str()));
+    let header = data.try_map_zip(&bundle, |data, bundle| {
+        format::BundleHeader::read(data, bundle.blocks()).map(|header| format!("{header:#?}"))
+    })?;
+    let mut text = super::diff_text(cx, header.as_deref());
+    if !text.is_empty() {
+        text.push('\n');
+    }
 
-    let mut text = String::new();
-    if !changes.removed.is_empty() {
-        write!(&mut text, "Removed: {:?}", changes.removed)?;
+    let changes = bundle.changes(|bundle| bundle.files().iter().map(|file| file.path.as_str()));
+    let is_serialized = |entry: &str| !entry.ends_with(".resS") && !entry.ends_with("resource");
+
+    let mut files = Vec::new();
+    let mut warnings = Vec::new();
+    let mut script_fields = ScriptFieldChanges::default();
+    for &entry in &changes.removed {
+        writeln!(&mut text, "--- Removed {entry} ---")?;
+        let size = bundle.old.file(entry).unwrap().size;
+        if is_serialized(entry) {
+            let data = bundle.old.read_at(entry)?.context("missing bundle entry")?;
+            let file = read_serializedfile(&data, env.old.unity_version()?)?;
+            let file = SerializedFileHandle::new(&env.old, &file, &data);
+            assets::summarize_objects(&file, &mut text)?;
+        } else {
+            writeln!(&mut text, "{size} bytes")?;
+        }
     }
-    if !changes.removed.is_empty() {
-        write!(&mut text, "Added: {:?}", changes.added)?;
+    for &entry in &changes.added {
+        writeln!(&mut text, "--- Added {entry} ---")?;
+        let size = bundle.new.file(entry).unwrap().size;
+        if is_serialized(entry) {
+            let data = bundle.new.read_at(entry)?.context("missing bundle entry")?;
+            let file = read_serializedfile(&data, env.new.unity_version()?)?;
+            let file = SerializedFileHandle::new(&env.new, &file, &data);
+            assets::list_objects(&file, &mut text)?;
+        } else {
+            writeln!(&mut text, "{size} bytes")?;
+        }
     }
-    for &bundle_path in &changes.same {
-        let contains_serialized_file =
-            !bundle_path.ends_with(".resS") && !bundle_path.ends_with("resource");
-
-        let size_changed = bundle
-            .map(|bundle| bundle.file(bundle_path).unwrap().size)
-            .changed();
-
-        if contains_serialized_file {
-            // writeln!(&mut text, "=== {bundle_path} ===")?;
-            let data = bundle.try_map(|bundle| bundle.read_at(bundle_path).transpose().unwrap())?;
-            let diff = diff_serializedfile(cx, &path.join(bundle_path), data.as_deref())?;
-            write!(&mut text, "{diff}")?;
-        } else if size_changed {
-            writeln!(&mut text, "=== {bundle_path} ===")?;
-            writeln!(&mut text, "changed")?;
+    for &entry in &changes.same {
+        let size = bundle.map(|bundle| bundle.file(entry).unwrap().size);
+
+        if is_serialized(entry) {
+            let data = bundle.try_map(|bundle| bundle.read_at(entry).transpose().unwrap())?;
+            let diff = diff_serializedfile_smart(
+                cx,
+                &path.join(entry),
+                data.as_deref(),
+                streamed.as_ref(),
+            )?;
+            write!(&mut text, "{}", diff.content)?;
+            // entries of one bundle can write files of the same name
+            let dir = PathBuf::from(sanitize_file_name(entry));
+            files.extend(
+                diff.files
+                    .into_iter()
+                    .map(|(name, data)| (dir.join(name), data)),
+            );
+            script_fields.merge(&diff.script_fields);
+            warnings.extend(
+                diff.warnings
+                    .into_iter()
+                    .map(|warning| format!("{entry}: {warning}")),
+            );
+        } else if size.changed() {
+            writeln!(&mut text, "--- Changed {entry} ---")?;
+            writeln!(
+                &mut text,
+                "{} -> {} bytes ({:+})",
+                size.old,
+                size.new,
+                size.new as i64 - size.old as i64
+            )?;
         }
     }
 
-    Ok(text)
+    Ok(DiffResult::diff_ext(text)
+        .with_files(files)
+        .with_warnings(warnings)
+        .with_script_fields(script_fields))
+}
+
+fn read_serializedfile(data: &[u8], unity_version: UnityVersion) -> Result<SerializedFile> {
+    let mut file = SerializedFile::from_reader(&mut Cursor::new(data))?;
+    file.m_UnityVersion.get_or_insert(unity_version);
+    Ok(file)
 }
 
 pub mod format {
     #![allow(non_snake_case, dead_code)]
+    use std::collections::BTreeSet;
+
+    use anyhow::{Context as _, Result};
+    use rabex::files::bundlefile::StorageBlock;
     use rabex_env::rabex::UnityVersion;
     use rabex_env::rabex::files::SerializedFile as SerializedFileRabex;
 
@@ -628,6 +787,88 @@ pub mod format {
             }
         }
     }
+
+    const COMPRESSION: [&str; 5] = ["None", "Lzma", "Lz4", "Lz4HC", "Lzham"];
+    const FLAGS: [(u32, &str); 4] = [
+        (0x40, "BlocksAndDirectoryInfoCombined"),
+        (0x80, "BlocksInfoAtTheEnd"),
+        (0x100, "OldWebPluginCompatibility"),
+        (0x200, "BlockInfoNeedPaddingAtStart"),
+    ];
+
+    fn compression(flags: u32) -> String {
+        let compression = flags & 0x3f;
+        COMPRESSION.get(compression as usize).map_or_else(
+            || format!("Unknown({compression})"),
+            |name| name.to_string(),
+        )
+    }
+
+    fn cstr<'a>(data: &mut &'a [u8]) -> Result<&'a str> {
+        let end = data
+            .iter()
+            .position(|&b| b == 0)
+            .context("truncated bundle header")?;
+        let string = std::str::from_utf8(&data[..end])?;
+        *data = &data[end + 1..];
+        Ok(string)
+    }
+
+    fn be_u32(data: &mut &[u8]) -> Result<u32> {
+        let (bytes, rest) = data
+            .split_first_chunk::<4>()
+            .context("truncated bundle header")?;
+        *data = rest;
+        Ok(u32::from_be_bytes(*bytes))
+    }
+
+    /// The UnityFS header, leaving out the sizes which change with any content
+    #[derive(Debug)]
+    pub struct BundleHeader<'a> {
+        signature: &'a str,
+        version: u32,
+        unity_version: &'a str,
+        unity_revision: &'a str,
+        compression: String,
+        flags: Vec<String>,
+        block_compression: BTreeSet<String>,
+    }
+
+    impl<'a> BundleHeader<'a> {
+        pub fn read(data: &'a [u8], blocks: &[StorageBlock]) -> Result<Self> {
+            let mut rest = data;
+            let signature = cstr(&mut rest)?;
+            let version = be_u32(&mut rest)?;
+            let unity_version = cstr(&mut rest)?;
+            let unity_revision = cstr(&mut rest)?;
+            // size: u64, compressed and uncompressed blocks info size: u32
+            rest = rest.get(16..).context("truncated bundle header")?;
+            let flags = be_u32(&mut rest)?;
+
+            let mut flag_names: Vec<String> = FLAGS
+                .iter()
+                .filter(|(flag, _)| flags & flag != 0)
+                .map(|(_, name)| name.to_string())
+                .collect();
+            let unknown = flags & !0x3ff;
+            if unknown != 0 {
+                flag_names.push(format!("{unknown:#x}"));
+            }
+
+            Ok(BundleHeader {
+                signature,
+                version,
+                unity_version,
+                unity_revision,
+                compression: compression(flags),
+                flags: flag_names,
+                block_compression: blocks
+                    .iter()
+                    .map(|block| compression(block.flags))
+                    .collect(),
+            })
+        }
+    }
 }
 
 fn name<R: BasedirEnvResolver, P: TypeTreeProvider>(
@@ -637,6 +878,9 @@ fn name<R: BasedirEnvResolver, P: TypeTreeProvider>(
     let name = val
         .get("m_Name")
         .and_then(serde_json::Value::as_str)
+        .filter(|name| !name.is_empty())
+        // shaders are named in their parsed form
+        .or_else(|| val.pointer("/m_ParsedForm/m_Name")?.as_str())
         .unwrap_or_default();
     let mut result = String::with_capacity(name.len() + 2);
     if !name.is_empty() {
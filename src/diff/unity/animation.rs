@@ -0,0 +1,543 @@
+//! Diffs AnimationClips by their curves and events instead of their keyframe arrays.
+//!
+//! Clips in a build store their curves in the muscle clip, bound by CRC32 hashes of the
+//! animated path and attribute. Paths are resolved from the hierarchy of the same file.
+
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow};
+use rabex::objects::ClassId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::diff::values::{self, array, string};
+use crate::old_new::OldNew;
+
+use super::matching;
+
+/// Attributes of common 2D components, to resolve the hashes of generic bindings
+const KNOWN_ATTRIBUTES: &[&str] = &[
+    "m_IsActive",
+    "m_Enabled",
+    "m_Sprite",
+    "m_FlipX",
+    "m_FlipY",
+    "m_Color.r",
+    "m_Color.g",
+    "m_Color.b",
+    "m_Color.a",
+    "m_SortingOrder",
+    "m_Offset.x",
+    "m_Offset.y",
+    "m_Size.x",
+    "m_Size.y",
+    "m_Radius",
+    "m_IsTrigger",
+    "m_Intensity",
+    "m_Range",
+    "m_Volume",
+    "m_Pitch",
+];
+
+/// Resolves the path hashes of generic bindings from the transforms of one version of a file.
+pub(super) struct BindingPaths<'a, P> {
+    file: &'a SerializedFileHandle<'a, GameFiles, P>,
+    paths: OnceCell<Result<FxHashMap<u32, String>, String>>,
+}
+
+impl<'a, P: TypeTreeProvider> BindingPaths<'a, P> {
+    pub fn new(file: &'a SerializedFileHandle<'a, GameFiles, P>) -> Self {
+        BindingPaths {
+            file,
+            paths: OnceCell::new(),
+        }
+    }
+
+    fn get(&self) -> Result<&FxHashMap<u32, String>> {
+        self.paths
+            .get_or_init(|| binding_paths(self.file).map_err(|e| format!("{e:#}")))
+            .as_ref()
+            .map_err(|e| anyhow!("{e}"))
+    }
+}
+
+/// Every path relative to any of its ancestors, as an animator below that ancestor would bind it.
+fn binding_paths<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+) -> Result<FxHashMap<u32, String>> {
+    let mut nodes = FxHashMap::default();
+    for transform in file.transforms()? {
+        let path_id = transform.path_id();
+        let transform = transform.read()?;
+        let go = file.deref(transform.m_GameObject)?.read()?;
+        nodes.insert(path_id, (transform.m_Father.m_PathID, go.m_Name));
+    }
+
+    let mut paths = FxHashMap::default();
+    paths.insert(crc32(b""), String::new());
+    for &start in nodes.keys() {
+        let mut path = String::new();
+        let mut current = start;
+        while let Some((father, name)) = nodes.get(&current) {
+            path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{name}/{path}")
+            };
+            paths.insert(crc32(path.as_bytes()), path.clone());
+            current = *father;
+        }
+    }
+    Ok(paths)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+struct Clip {
+    length: f32,
+    sample_rate: f32,
+    /// `'path' Class.attribute` -> keys
+    curves: BTreeMap<String, Vec<(f32, String)>>,
+    events: Vec<Event>,
+}
+
+struct Event {
+    function: String,
+    rendered: String,
+}
+
+/// Diffs the length, frame rate, curves and events of an AnimationClip,
+/// and the remaining fields as JSON.
+pub(super) fn diff_clip<P: TypeTreeProvider>(
+    cx: &Context,
+    mut value: OldNew<serde_json::Value>,
+    paths: OldNew<&BindingPaths<'_, P>>,
+    class: &str,
+) -> Result<String> {
+    let paths = paths.try_map(BindingPaths::get)?;
+    let clip = value
+        .as_ref()
+        .map_zip(&paths, |value, paths| Clip::decode(value, paths));
+    strip_decoded(&mut value.old);
+    strip_decoded(&mut value.new);
+
+    let mut out = String::new();
+    let length = clip.as_ref().map(|clip| clip.length);
+    if length.changed() {
+        writeln!(out, "length {}s -> {}s", length.old, length.new)?;
+    }
+    let sample_rate = clip.as_ref().map(|clip| clip.sample_rate);
+    if sample_rate.changed() {
+        writeln!(
+            out,
+            "sample rate {} -> {}",
+            sample_rate.old, sample_rate.new
+        )?;
+    }
+
+    let curves = clip.as_ref().map(|clip| &clip.curves);
+    let changes = curves.changes(|curves| curves.keys());
+    for curve in changes.removed {
+        writeln!(out, "curve {curve} removed")?;
+    }
+    for curve in changes.added {
+        writeln!(
+            out,
+            "curve {curve} added ({} keys)",
+            curves.new[curve].len()
+        )?;
+    }
+    for curve in changes.same {
+        let keys = curves.map(|curves| &curves[curve][..]);
+        let key_diff = diff_keys(keys)?;
+        if !key_diff.is_empty() {
+            writeln!(out, "curve {curve}")?;
+            out.push_str(&key_diff);
+        }
+    }
+
+    diff_events(&mut out, clip.as_ref().map(|clip| &clip.events[..]))?;
+
+    let json = crate::diff::diff_json(cx, value.as_ref(), class)?;
+    if !json.is_empty() {
+        out.push_str(&json);
+    }
+    Ok(out.trim_end().to_owned())
+}
+
+/// Keys are paired by index if there are as many as before, which shows retimed keys as such.
+fn diff_keys(keys: OldNew<&[(f32, String)]>) -> Result<String> {
+    let mut out = String::new();
+
+    if keys.old.len() == keys.new.len() {
+        for (i, (old, new)) in keys.old.iter().zip(keys.new).enumerate() {
+            if old.0 != new.0 {
+                writeln!(out, "  key {i} time {} -> {}", old.0, new.0)?;
+            }
+            if old.1 != new.1 {
+                writeln!(out, "  key {i} value {} -> {}", old.1, new.1)?;
+            }
+        }
+        return Ok(out);
+    }
+
+    writeln!(out, "  {} keys -> {}", keys.old.len(), keys.new.len())?;
+    for (time, value) in keys.old {
+        match keys.new.iter().find(|(new_time, _)| new_time == time) {
+            None => writeln!(out, "  - {time}: {value}")?,
+            Some((_, new)) if new != value => writeln!(out, "  {time}: {value} -> {new}")?,
+            Some(_) => {}
+        }
+    }
+    for (time, value) in keys.new {
+        if !keys.old.iter().any(|(old_time, _)| old_time == time) {
+            writeln!(out, "  + {time}: {value}")?;
+        }
+    }
+    Ok(out)
+}
+
+fn diff_events(out: &mut String, events: OldNew<&[Event]>) -> Result<()> {
+    let pairs = matching::align(events.map(|events| {
+        events
+            .iter()
+            .map(|event| event.function.as_str())
+            .collect::<Vec<_>>()
+    }));
+
+    let mut section = String::new();
+    for pair in pairs {
+        match pair {
+            (Some(i), None) => writeln!(section, "  - {}", events.old[i].rendered)?,
+            (None, Some(i)) => writeln!(section, "  + {}", events.new[i].rendered)?,
+            (Some(old), Some(new)) => {
+                let rendered = OldNew::new(&events.old[old], &events.new[new])
+                    .map(|event| event.rendered.as_str());
+                if rendered.changed() {
+                    writeln!(section, "  {} -> {}", rendered.old, rendered.new)?;
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    if !section.is_empty() {
+        writeln!(out, "events")?;
+        out.push_str(&section);
+    }
+    Ok(())
+}
+
+/// Removes what [`Clip::decode`] covers, so that only the remaining fields are diffed as JSON.
+fn strip_decoded(value: &mut Value) {
+    let Some(map) = value.as_object_mut() else {
+        return;
+    };
+    for key in [
+        "m_RotationCurves",
+        "m_CompressedRotationCurves",
+        "m_EulerCurves",
+        "m_PositionCurves",
+        "m_ScaleCurves",
+        "m_FloatCurves",
+        "m_PPtrCurves",
+        "m_SampleRate",
+        "m_ClipBindingConstant",
+        "m_Events",
+    ] {
+        map.remove(key);
+    }
+    if let Some(muscle_clip) = map.get_mut("m_MuscleClip").and_then(Value::as_object_mut) {
+        // the index and value arrays are derived from the curves
+        for key in [
+            "m_Clip",
+            "m_StartTime",
+            "m_StopTime",
+            "m_IndexArray",
+            "m_ValueArrayDelta",
+            "m_ValueArrayReferencePose",
+        ] {
+            muscle_clip.remove(key);
+        }
+    }
+}
+
+impl Clip {
+    fn decode(value: &Value, paths: &FxHashMap<u32, String>) -> Clip {
+        let number = |pointer: &str| value.pointer(pointer).and_then(Value::as_f64);
+
+        let mut curves = BTreeMap::new();
+        editor_curves(value, &mut curves);
+        muscle_curves(value, paths, &mut curves);
+
+        let length = match (
+            number("/m_MuscleClip/m_StartTime"),
+            number("/m_MuscleClip/m_StopTime"),
+        ) {
+            (Some(start), Some(stop)) => (stop - start) as f32,
+            _ => curves
+                .values()
+                .flat_map(|keys| keys.iter().map(|&(time, _)| time))
+                .fold(0.0, f32::max),
+        };
+
+        let events = array(value, "m_Events")
+            .iter()
+            .map(|event| {
+                let function = string(event, "functionName").to_owned();
+                let mut params = Vec::new();
+                if let Some(data) = event.get("data").and_then(Value::as_str)
+                    && !data.is_empty()
+                {
+                    params.push(format!("{data:?}"));
+                }
+                if let Some(float) = event.get("floatParameter").and_then(Value::as_f64)
+                    && float != 0.0
+                {
+                    params.push(values::number(float));
+                }
+                if let Some(int) = event.get("intParameter").and_then(Value::as_i64)
+                    && int != 0
+                {
+                    params.push(int.to_string());
+                }
+                if let Some(object) = event.get("objectReferenceParameter")
+                    && !object.is_null()
+                {
+                    params.push(values::render_any(object));
+                }
+
+                let time = values::number(event.get("time").and_then(Value::as_f64).unwrap_or(0.0));
+                let rendered = format!("{time}s {function}({})", params.join(", "));
+                Event { function, rendered }
+            })
+            .collect();
+
+        Clip {
+            length,
+            sample_rate: number("/m_SampleRate").unwrap_or(0.0) as f32,
+            curves,
+            events,
+        }
+    }
+}
+
+/// The curves of legacy clips, which keep their paths and attribute names.
+fn editor_curves(value: &Value, curves: &mut BTreeMap<String, Vec<(f32, String)>>) {
+    for (field, attribute) in [
+        ("m_PositionCurves", "Transform.m_LocalPosition"),
+        ("m_RotationCurves", "Transform.m_LocalRotation"),
+        ("m_EulerCurves", "Transform.m_LocalEulerAngles"),
+        ("m_ScaleCurves", "Transform.m_LocalScale"),
+    ] {
+        for curve in array(value, field) {
+            let keys = curve.pointer("/curve/m_Curve").map(keyframes);
+            curves.insert(
+                format!("'{}' {attribute}", string(curve, "path")),
+                keys.unwrap_or_default(),
+            );
+        }
+    }
+
+    for curve in array(value, "m_FloatCurves") {
+        let keys = curve.pointer("/curve/m_Curve").map(keyframes);
+        curves.insert(binding_name(curve), keys.unwrap_or_default());
+    }
+    for curve in array(value, "m_PPtrCurves") {
+        let keys = curve.get("curve").map(keyframes);
+        curves.insert(binding_name(curve), keys.unwrap_or_default());
+    }
+}
+
+fn binding_name(curve: &Value) -> String {
+    let path = string(curve, "path");
+    let attribute = string(curve, "attribute");
+    let class_id = curve.get("classID").and_then(Value::as_i64).unwrap_or(-1);
+    match curve.get("script").filter(|script| !script.is_null()) {
+        Some(script) => format!("'{path}' {}.{attribute}", values::render_any(script)),
+        None => format!("'{path}' {:?}.{attribute}", ClassId(class_id as i32)),
+    }
+}
+
+fn keyframes(keys: &Value) -> Vec<(f32, String)> {
+    keys.as_array()
+        .into_iter()
+        .flatten()
+        .map(|key| {
+            let time = key.get("time").and_then(Value::as_f64).unwrap_or(0.0) as f32;
+            let value = key.get("value").map(values::render_any).unwrap_or_default();
+            (time, value)
+        })
+        .collect()
+}
+
+/// A curve of the muscle clip and the binding it animates.
+struct Binding {
+    name: String,
+    /// Index into `pptrCurveMapping` instead of a float value
+    pptr: bool,
+}
+
+/// The streamed, dense and constant curves of a built clip, in the order of its generic bindings.
+fn muscle_curves(
+    value: &Value,
+    paths: &FxHashMap<u32, String>,
+    curves: &mut BTreeMap<String, Vec<(f32, String)>>,
+) {
+    let Some(clip) = value.pointer("/m_MuscleClip/m_Clip/data") else {
+        return;
+    };
+    let bindings = bindings(value, paths);
+    let pptr_mapping = value
+        .pointer("/m_ClipBindingConstant/pptrCurveMapping")
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice);
+
+    let mut push = |index: usize, time: f32, value: f32| {
+        let Some(binding) = bindings.get(index) else {
+            return;
+        };
+        let value = if binding.pptr {
+            pptr_mapping
+                .get(value as usize)
+                .map_or_else(|| format!("pptr #{value}"), values::render_any)
+        } else {
+            values::number(value.into())
+        };
+        curves
+            .entry(binding.name.clone())
+            .or_default()
+            .push((time, value));
+    };
+
+    let streamed = clip.get("m_StreamedClip");
+    let streamed_count = streamed
+        .and_then(|streamed| streamed.get("curveCount"))
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as usize;
+    let data: Vec<u8> = streamed
+        .map(|streamed| array(streamed, "data"))
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_u64)
+        .flat_map(|word| (word as u32).to_le_bytes())
+        .collect();
+    let word = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let mut offset = 0;
+    // frames of `time, key count, [curve index, 4 hermite coefficients]`
+    while let (Some(time), Some(count)) = (word(offset), word(offset + 4)) {
+        let time = f32::from_bits(time);
+        offset += 8;
+        for _ in 0..count {
+            let (Some(index), Some(value)) = (word(offset), word(offset + 16)) else {
+                return;
+            };
+            // the first and last frames are sentinels outside of the clip
+            if time.is_finite() && time.abs() < 1e30 {
+                push(index as usize, time, f32::from_bits(value));
+            }
+            offset += 20;
+        }
+    }
+
+    let dense = clip.get("m_DenseClip");
+    let number = |value: Option<&Value>, key: &str| {
+        value
+            .and_then(|value| value.get(key))
+            .and_then(Value::as_f64)
+            .unwrap_or(0.0)
+    };
+    let dense_count = number(dense, "m_CurveCount") as usize;
+    let sample_rate = number(dense, "m_SampleRate") as f32;
+    let begin_time = number(dense, "m_BeginTime") as f32;
+    if dense_count > 0 && sample_rate > 0.0 {
+        let samples = dense
+            .map(|dense| array(dense, "m_SampleArray"))
+            .unwrap_or_default();
+        for (frame, samples) in samples.chunks_exact(dense_count).enumerate() {
+            let time = begin_time + frame as f32 / sample_rate;
+            for (curve, sample) in samples.iter().enumerate() {
+                let sample = sample.as_f64().unwrap_or(0.0) as f32;
+                push(streamed_count + curve, time, sample);
+            }
+        }
+    }
+
+    let constant = clip
+        .get("m_ConstantClip")
+        .map(|constant| array(constant, "data"))
+        .unwrap_or_default();
+    for (curve, sample) in constant.iter().enumerate() {
+        let sample = sample.as_f64().unwrap_or(0.0) as f32;
+        push(streamed_count + dense_count + curve, 0.0, sample);
+    }
+}
+
+/// One entry per curve index. Transform bindings span one curve per vector component.
+fn bindings(value: &Value, paths: &FxHashMap<u32, String>) -> Vec<Binding> {
+    let generic_bindings = value
+        .pointer("/m_ClipBindingConstant/genericBindings")
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice);
+    let attributes: FxHashMap<u32, &str> = KNOWN_ATTRIBUTES
+        .iter()
+        .map(|&attribute| (crc32(attribute.as_bytes()), attribute))
+        .collect();
+
+    let mut bindings = Vec::new();
+    for binding in generic_bindings {
+        let field = |key: &str| binding.get(key).and_then(Value::as_u64).unwrap_or(0);
+        let path_hash = field("path") as u32;
+        let path = match paths.get(&path_hash) {
+            Some(path) => format!("'{path}'"),
+            None => format!("path#{path_hash:08x}"),
+        };
+        let attribute = field("attribute") as u32;
+        let class_id = ClassId(field("typeID") as i32);
+        let pptr = field("isPPtrCurve") != 0;
+
+        let (attribute, components): (String, &[&str]) = match (class_id, attribute) {
+            (ClassId::Transform, 1) => ("m_LocalPosition".into(), &["x", "y", "z"]),
+            (ClassId::Transform, 2) => ("m_LocalRotation".into(), &["x", "y", "z", "w"]),
+            (ClassId::Transform, 3) => ("m_LocalScale".into(), &["x", "y", "z"]),
+            (ClassId::Transform, 4) => ("m_LocalEulerAngles".into(), &["x", "y", "z"]),
+            (_, attribute) => match attributes.get(&attribute) {
+                Some(name) => (name.to_string(), &[]),
+                None => (format!("attribute#{attribute:08x}"), &[]),
+            },
+        };
+        let class = match binding.get("script").filter(|script| !script.is_null()) {
+            Some(script) => values::render_any(script),
+            None => format!("{class_id:?}"),
+        };
+
+        if components.is_empty() {
+            let name = format!("{path} {class}.{attribute}");
+            bindings.push(Binding { name, pptr });
+        }
+        for component in components {
+            let name = format!("{path} {class}.{attribute}.{component}");
+            bindings.push(Binding { name, pptr });
+        }
+    }
+    bindings
+}
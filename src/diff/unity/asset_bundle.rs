@@ -0,0 +1,195 @@
+//! The `AssetBundle` object of a bundle, which maps asset paths to objects and lists what to load with them,
+//! and the `PreloadData` of scene bundles.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::diff::{Context, values};
+use crate::old_new::OldNew;
+
+/// How many added or removed preloaded assets to name before only counting them
+const MAX_LISTED: usize = 8;
+
+/// The objects at one container path, and the union of what is preloaded for them
+#[derive(Default)]
+struct ContainerEntry {
+    assets: BTreeSet<String>,
+    preload: BTreeSet<String>,
+}
+
+/// Diffs the container by asset path instead of by index, and the preload table by what each path loads.
+pub(super) fn diff_asset_bundle(cx: &Context, value: OldNew<Value>, class: &str) -> Result<String> {
+    let mut out = String::new();
+
+    let main_asset = value
+        .as_ref()
+        .map(|value| value.get("m_MainAsset").map(main_asset).unwrap_or_default());
+    if main_asset.changed() {
+        writeln!(out, "main asset {} -> {}", main_asset.old, main_asset.new)?;
+    }
+
+    let container = value.as_ref().map(container);
+    let container = container.as_ref();
+    let paths = container.changes(|container| container.keys());
+    for path in paths.removed {
+        let assets = join(&container.old[path].assets);
+        writeln!(out, "container '{path}' removed ({assets})")?;
+    }
+    for path in paths.added {
+        let assets = join(&container.new[path].assets);
+        writeln!(out, "container '{path}' added ({assets})")?;
+    }
+    for path in &paths.same {
+        let assets = container.map(|container| join(&container[*path].assets));
+        if assets.changed() {
+            writeln!(out, "container '{path}' {} -> {}", assets.old, assets.new)?;
+        }
+    }
+
+    let dependencies = value.as_ref().map(|value| strings(value, "m_Dependencies"));
+    values::diff_set(&mut out, "dependency", dependencies.as_ref())?;
+
+    let preload_len = value
+        .as_ref()
+        .map(|value| values::array(value, "m_PreloadTable").len());
+    if preload_len.changed() {
+        writeln!(
+            out,
+            "preload table {} -> {} entries",
+            preload_len.old, preload_len.new
+        )?;
+    }
+    for path in paths.same {
+        let preload = container.map(|container| &container[path].preload);
+        diff_preload(&mut out, &format!("preload of '{path}'"), preload)?;
+    }
+
+    out.push_str(&values::diff_json_without(
+        cx,
+        value,
+        &[
+            "m_MainAsset",
+            "m_Container",
+            "m_Dependencies",
+            "m_PreloadTable",
+        ],
+        class,
+    )?);
+
+    Ok(out)
+}
+
+/// Diffs the assets a scene bundle loads with its scene, which like the preload table shift with every change.
+pub(super) fn diff_preload_data(cx: &Context, value: OldNew<Value>, class: &str) -> Result<String> {
+    let mut out = String::new();
+
+    let assets = value.as_ref().map(|value| {
+        values::array(value, "m_Assets")
+            .iter()
+            .map(target)
+            .collect::<BTreeSet<_>>()
+    });
+    diff_preload(&mut out, "preloaded assets", assets.as_ref())?;
+
+    let dependencies = value.as_ref().map(|value| strings(value, "m_Dependencies"));
+    values::diff_set(&mut out, "dependency", dependencies.as_ref())?;
+
+    out.push_str(&values::diff_json_without(
+        cx,
+        value,
+        &["m_Assets", "m_Dependencies"],
+        class,
+    )?);
+
+    Ok(out)
+}
+
+/// `m_Container` is a list of `first`/`second` pairs, with the same path repeated for e.g. the sprites of a texture.
+/// Each entry's `preloadIndex` and `preloadSize` select the slice of `m_PreloadTable` that is loaded with it.
+fn container(value: &Value) -> BTreeMap<String, ContainerEntry> {
+    let preload_table = values::array(value, "m_PreloadTable");
+
+    let mut container = BTreeMap::<String, ContainerEntry>::new();
+    for (path, info) in values::pairs(value, "m_Container") {
+        let Some(path) = path.as_str() else {
+            continue;
+        };
+        let entry = container.entry(path.to_owned()).or_default();
+        if let Some(asset) = info.get("asset") {
+            entry.assets.insert(target(asset));
+        }
+
+        let index = info.get("preloadIndex").and_then(Value::as_u64);
+        let size = info.get("preloadSize").and_then(Value::as_u64);
+        if let (Some(index), Some(size)) = (index, size) {
+            let preload = preload_table
+                .iter()
+                .skip(index as usize)
+                .take(size as usize);
+            entry.preload.extend(preload.map(target));
+        }
+    }
+    container
+}
+
+fn main_asset(info: &Value) -> String {
+    info.get("asset").map(target).unwrap_or_default()
+}
+
+/// A qualified PPtr as its type and name
+fn target(pptr: &Value) -> String {
+    match (
+        pptr.get("type").and_then(Value::as_str),
+        pptr.get("$target"),
+    ) {
+        (Some(class), Some(name)) => format!("{class} {}", values::render_any(name)),
+        _ if pptr.is_null() => "none".to_owned(),
+        _ => values::render_any(pptr),
+    }
+}
+
+fn strings(value: &Value, key: &str) -> BTreeSet<String> {
+    values::array(value, key)
+        .iter()
+        .filter_map(|value| Some(value.as_str()?.to_owned()))
+        .collect()
+}
+
+fn join(assets: &BTreeSet<String>) -> String {
+    assets
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Names the added and removed assets, or counts them if there are many
+fn diff_preload(out: &mut String, label: &str, assets: OldNew<&BTreeSet<String>>) -> Result<()> {
+    let changes = assets.changes(|assets| assets.iter());
+    if changes.removed.is_empty() && changes.added.is_empty() {
+        return Ok(());
+    }
+
+    if changes.removed.len() + changes.added.len() > MAX_LISTED {
+        writeln!(
+            out,
+            "{label}: {} added, {} removed ({} -> {} assets)",
+            changes.added.len(),
+            changes.removed.len(),
+            assets.old.len(),
+            assets.new.len()
+        )?;
+    } else {
+        writeln!(out, "{label}:")?;
+        for asset in changes.removed {
+            writeln!(out, "  - {asset}")?;
+        }
+        for asset in changes.added {
+            writeln!(out, "  + {asset}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use rustc_hash::FxHashMap;
+use serde_derive::Deserialize;
+
+use crate::diff::Context;
+use crate::old_new::OldNew;
+
+use super::animation::{self, BindingPaths};
+use super::asset_bundle;
+use super::audio::{self, AudioClips};
+use super::geometry;
+use super::localization::{self, Sheets};
+use super::material;
+use super::playmaker;
+use super::settings;
+use super::streamed::StreamedData;
+use super::text_asset;
+use super::texture::{self, Textures};
+use super::{ScriptFieldChanges, qualify_pptrs, raw_data, sanitize_file_name};
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct Named {
+    #[serde(default)]
+    m_Name: String,
+}
+
+/// Identifies an asset across versions independent of its path ID.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct AssetKey {
+    pub class_id: ClassId,
+    script: Option<String>,
+    pub name: String,
+    /// Disambiguates assets with the same class, script and name
+    pub index: usize,
+}
+impl Display for AssetKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.class_id)?;
+        if let Some(script) = &self.script {
+            write!(f, " {script}")?;
+        }
+        if !self.name.is_empty() {
+            write!(f, " '{}'", self.name)?;
+        }
+        if self.index > 0 {
+            write!(f, " #{}", self.index)?;
+        }
+        Ok(())
+    }
+}
+impl AssetKey {
+    /// Name for files written for this asset, like `Knight.Texture2D`.
+    fn file_name(&self) -> String {
+        let mut name = sanitize_file_name(&self.name);
+        if self.index > 0 {
+            write!(name, "#{}", self.index).unwrap();
+        }
+        write!(name, ".{:?}", self.class_id).unwrap();
+        name
+    }
+}
+
+/// Loads the pixel and audio data of one version of a serialized file, which is usually streamed out of it,
+/// and the paths animation clips bind to.
+struct Resources<'a, P> {
+    textures: Textures<'a, P>,
+    clips: AudioClips<'a, P>,
+    binding_paths: BindingPaths<'a, P>,
+}
+
+/// Diffs the objects of a serialized file that aren't part of the GameObject hierarchy,
+/// like ScriptableObjects, materials or animation clips.
+pub(super) fn diff_assets<P: TypeTreeProvider>(
+    cx: &Context,
+    file: OldNew<&SerializedFileHandle<'_, GameFiles, P>>,
+    streamed: OldNew<&StreamedData>,
+    out: &mut String,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    warnings: &mut Vec<String>,
+    script_fields: &mut ScriptFieldChanges,
+) -> Result<()> {
+    let assets = file.try_map(|file| collect_assets(cx, file))?;
+    let changes = assets.as_ref().changes(|assets| assets.keys());
+    let resources = file.map_zip(&streamed, |file, streamed| Resources {
+        textures: Textures::new(file, streamed),
+        clips: AudioClips::new(file, streamed),
+        binding_paths: BindingPaths::new(file),
+    });
+    // language sheets are diffed together across all languages
+    let sheets = file.map_zip(&assets, |file, assets| Sheets::read(file, assets));
+    localization::diff_sheets(sheets.as_ref(), out, warnings)?;
+
+    for key in changes.removed {
+        if sheets.old.contains(key) {
+            continue;
+        }
+        writeln!(out, "--- Removed {key} ---")?;
+        if let Some(description) = describe(
+            cx,
+            file.old,
+            &resources.old,
+            key,
+            assets.old[key],
+            "old",
+            files,
+        ) {
+            out.push_str(&description);
+        }
+    }
+    for key in changes.added {
+        if sheets.new.contains(key) {
+            continue;
+        }
+        writeln!(out, "--- Added {key} ---")?;
+        if let Some(description) = describe(
+            cx,
+            file.new,
+            &resources.new,
+            key,
+            assets.new[key],
+            "new",
+            files,
+        ) {
+            out.push_str(&description);
+        } else if let Some(limits) = cx.unity_dump_objects {
+            dump_asset(out, file.new, assets.new[key], limits.max_size)?;
+        }
+    }
+    for key in changes.same {
+        if sheets.old.contains(key) || sheets.new.contains(key) {
+            continue;
+        }
+        let path_id = assets.as_ref().map(|assets| assets[key]);
+        match diff_asset(
+            cx,
+            file,
+            resources.as_ref(),
+            path_id,
+            key,
+            files,
+            script_fields,
+        ) {
+            Ok(diff) if diff.is_empty() => {}
+            Ok(diff) => {
+                writeln!(out, "--- Changed {key} ---")?;
+                out.push_str(&diff);
+            }
+            Err(e) => {
+                writeln!(out, "--- Failed to diff {key} ---")?;
+                warnings.push(format!("Skipping {key} (Path ID {}): {e:#}", path_id.new));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_assets<P: TypeTreeProvider>(
+    cx: &Context,
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+) -> Result<BTreeMap<AssetKey, PathId>> {
+    let mut assets = BTreeMap::new();
+    let mut seen = FxHashMap::<(ClassId, Option<String>, String), usize>::default();
+
+    for info in file.file.objects() {
+        let object = file.object_at::<serde_json::Value>(info.m_PathID)?;
+        let class_id = object.class_id();
+
+        if matches!(
+            class_id,
+            ClassId::GameObject | ClassId::Transform | ClassId::RectTransform
+        ) || !cx.unity_filter.matches(&object)
+        {
+            continue;
+        }
+        // components are covered by the hierarchy diff
+        if object
+            .object
+            .tt
+            .children
+            .iter()
+            .any(|field| field.m_Name == "m_GameObject")
+        {
+            continue;
+        }
+
+        let script = object
+            .mono_script()?
+            .map(|script| script.full_name().into_owned());
+        // reading only the name skips building JSON for texture, audio and binary text data
+        let name = file.object_at::<Named>(info.m_PathID)?.read()?.m_Name;
+
+        let index = seen
+            .entry((class_id, script.clone(), name.clone()))
+            .or_default();
+        assets.insert(
+            AssetKey {
+                class_id,
+                script,
+                name,
+                index: *index,
+            },
+            info.m_PathID,
+        );
+        *index += 1;
+    }
+
+    Ok(assets)
+}
+
+/// Writes one line per object, for serialized files that only exist in one version.
+pub(super) fn list_objects<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    out: &mut String,
+) -> Result<()> {
+    for info in file.file.objects() {
+        let object = file.object_at::<serde_json::Value>(info.m_PathID)?;
+        write!(out, "{} {:?}", info.m_PathID, object.class_id())?;
+        if let Some(script) = object.mono_script()? {
+            write!(out, " {}", script.full_name())?;
+        }
+        let name = file.object_at::<Named>(info.m_PathID)?.read()?.m_Name;
+        if !name.is_empty() {
+            write!(out, " '{name}'")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes how many objects of each class a serialized file contains.
+pub(super) fn summarize_objects<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    out: &mut String,
+) -> Result<()> {
+    let mut counts = BTreeMap::<String, usize>::new();
+    for info in file.file.objects() {
+        let object = file.object_at::<serde_json::Value>(info.m_PathID)?;
+        *counts
+            .entry(format!("{:?}", object.class_id()))
+            .or_default() += 1;
+    }
+    let total: usize = counts.values().sum();
+    writeln!(out, "{total} objects")?;
+    for (class, count) in counts {
+        writeln!(out, "  {count:>5} {class}")?;
+    }
+    Ok(())
+}
+
+fn diff_asset<P: TypeTreeProvider>(
+    cx: &Context,
+    file: OldNew<&SerializedFileHandle<'_, GameFiles, P>>,
+    resources: OldNew<&Resources<'_, P>>,
+    path_id: OldNew<PathId>,
+    key: &AssetKey,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    script_fields: &mut ScriptFieldChanges,
+) -> Result<String> {
+    let object = file.try_map_zip(&path_id, |file, &path_id| {
+        file.object_at::<serde_json::Value>(path_id)
+    })?;
+    if !object.as_ref().map(raw_data).changed() {
+        // the data may change without the object itself changing, e.g. in the `.resS` file.
+        // Sprites cut from a changed texture are shown by the texture's diff.
+        let streamed = match key.class_id {
+            ClassId::Texture2D => resources.try_map_zip(&path_id, |resources, &path_id| {
+                resources.textures.streamed_data(path_id)
+            })?,
+            ClassId::AudioClip => resources.try_map_zip(&path_id, |resources, &path_id| {
+                resources.clips.streamed_data(path_id)
+            })?,
+            _ => OldNew::new(None, None),
+        };
+        if !streamed.changed() {
+            return Ok(String::new());
+        }
+    }
+
+    let name = key.file_name();
+    match key.class_id {
+        ClassId::Texture2D => {
+            let textures = resources.map(|resources| &resources.textures);
+            return texture::diff_texture(textures, path_id, &name, files);
+        }
+        ClassId::Sprite => {
+            let textures = resources.map(|resources| &resources.textures);
+            return texture::diff_sprite(cx, textures, path_id, &name, files);
+        }
+        ClassId::AudioClip => {
+            let clips = resources.map(|resources| &resources.clips);
+            return audio::diff_audio(cx, clips, path_id, &name, files);
+        }
+        ClassId::TextAsset => return text_asset::diff_text_asset(cx, file, path_id),
+        _ => {}
+    }
+
+    let mut value = object.as_ref().try_map(|object| object.read())?;
+    if let Some(script) = &key.script {
+        script_fields.record(
+            script,
+            object.as_ref().map(|object| &*object.object.tt),
+            &mut value,
+        );
+    }
+
+    qualify_pptrs(file.old, &mut value.old).context("qualifying pptrs")?;
+    qualify_pptrs(file.new, &mut value.new).context("qualifying pptrs")?;
+
+    let class = format!("{:?}", key.class_id);
+    let mut diff = match key.class_id {
+        ClassId::AnimationClip => {
+            let paths = resources.map(|resources| &resources.binding_paths);
+            animation::diff_clip(cx, value, paths, &class)?
+        }
+        ClassId::Mesh => geometry::diff_mesh(cx, value, &class)?,
+        ClassId::Material => material::diff_material(cx, value, &class)?,
+        ClassId::Shader => material::diff_shader(cx, value, &class)?,
+        ClassId::AssetBundle => asset_bundle::diff_asset_bundle(cx, value, &class)?,
+        ClassId::PreloadData => asset_bundle::diff_preload_data(cx, value, &class)?,
+        class_id if settings::is_settings(class_id) => {
+            settings::diff_settings(cx, file, class_id, value, &class)?
+        }
+        ClassId::MonoBehaviour if playmaker::is_fsm(value.as_ref()) => {
+            playmaker::diff(cx, value, &class)?
+        }
+        _ => crate::diff::diff_json(cx, value.as_ref(), &class)?,
+    };
+    if !diff.is_empty() {
+        diff.push('\n');
+    }
+    Ok(diff)
+}
+
+/// Summarizes an added or removed asset whose data isn't useful as JSON,
+/// and writes it next to the diff. `None` for all other assets.
+fn describe<P: TypeTreeProvider>(
+    cx: &Context,
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    resources: &Resources<'_, P>,
+    key: &AssetKey,
+    path_id: PathId,
+    version: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Option<String> {
+    let name = key.file_name();
+    let description = match key.class_id {
+        ClassId::Texture2D | ClassId::Sprite => texture::describe(
+            &resources.textures,
+            key.class_id,
+            path_id,
+            &name,
+            version,
+            files,
+        ),
+        ClassId::AudioClip => audio::describe(cx, &resources.clips, path_id, &name, version, files),
+        ClassId::TextAsset => text_asset::describe(cx, file, path_id),
+        _ => return None,
+    };
+    Some(description.unwrap_or_else(|e| format!("could not load: {e:#}\n")))
+}
+
+fn dump_asset<P: TypeTreeProvider>(
+    out: &mut String,
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    path_id: PathId,
+    max_size: usize,
+) -> Result<()> {
+    let object = file.object_at::<serde_json::Value>(path_id)?;
+    let mut value = object.read()?;
+    qualify_pptrs(file, &mut value).context("qualifying pptrs")?;
+
+    let json = serde_json::to_string_pretty(&value)?;
+    if json.len() > max_size {
+        writeln!(out, "({} bytes, exceeds dump size limit)", json.len())?;
+    } else {
+        writeln!(out, "{json}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,378 @@
+use std::fmt::Write;
+use std::hash::Hasher;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result, bail, ensure};
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use rustc_hash::FxHasher;
+use serde_derive::Deserialize;
+
+use crate::diff::Context;
+use crate::old_new::OldNew;
+
+use super::streamed::StreamedData;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct AudioClip {
+    m_Channels: i32,
+    m_Frequency: i32,
+    m_Length: f32,
+    m_Resource: StreamedResource,
+    m_CompressionFormat: i32,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct StreamedResource {
+    m_Source: String,
+    m_Offset: u64,
+    m_Size: u64,
+}
+
+/// An audio clip with its FSB5 data resolved from the `.resource` file.
+struct Clip {
+    channels: i32,
+    frequency: i32,
+    length: f32,
+    compression: i32,
+    data: Vec<u8>,
+}
+impl Clip {
+    fn hash(&self) -> u64 {
+        let mut hasher = FxHasher::default();
+        hasher.write(&self.data);
+        hasher.finish()
+    }
+}
+
+/// Loads the audio clips of one version of a serialized file.
+pub(super) struct AudioClips<'a, P> {
+    file: &'a SerializedFileHandle<'a, GameFiles, P>,
+    streamed: &'a StreamedData<'a>,
+}
+
+impl<'a, P: TypeTreeProvider> AudioClips<'a, P> {
+    pub fn new(
+        file: &'a SerializedFileHandle<'a, GameFiles, P>,
+        streamed: &'a StreamedData<'a>,
+    ) -> Self {
+        AudioClips { file, streamed }
+    }
+
+    fn get(&self, path_id: PathId) -> Result<Clip> {
+        let clip = self
+            .file
+            .object_at::<AudioClip>(path_id)?
+            .read()
+            .context("only audio clips since Unity 5 are supported")?;
+
+        let data = self.read_resource(&clip.m_Resource)?.unwrap_or_default();
+
+        Ok(Clip {
+            channels: clip.m_Channels,
+            frequency: clip.m_Frequency,
+            length: clip.m_Length,
+            compression: clip.m_CompressionFormat,
+            data,
+        })
+    }
+
+    /// The FSB5 data of a clip, which can change without the clip object changing.
+    pub fn streamed_data(&self, path_id: PathId) -> Result<Option<Vec<u8>>> {
+        let clip = self
+            .file
+            .object_at::<AudioClip>(path_id)?
+            .read()
+            .context("only audio clips since Unity 5 are supported")?;
+        self.read_resource(&clip.m_Resource)
+    }
+
+    fn read_resource(&self, resource: &StreamedResource) -> Result<Option<Vec<u8>>> {
+        if resource.m_Source.is_empty() {
+            return Ok(None);
+        }
+        let data = self
+            .streamed
+            .read(
+                &resource.m_Source,
+                resource.m_Offset,
+                resource.m_Size.try_into()?,
+            )
+            .context("reading audio resource")?;
+        Ok(Some(data))
+    }
+}
+
+pub(super) fn diff_audio<P: TypeTreeProvider>(
+    cx: &Context,
+    clips: OldNew<&AudioClips<'_, P>>,
+    path_id: OldNew<PathId>,
+    name: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let clip = clips.try_map_zip(&path_id, |clips, &path_id| clips.get(path_id))?;
+    let clip = clip.as_ref();
+    let mut out = String::new();
+
+    let length = clip.map(|clip| clip.length);
+    if length.changed() {
+        writeln!(out, "length {}s -> {}s", length.old, length.new)?;
+    }
+    let channels = clip.map(|clip| clip.channels);
+    if channels.changed() {
+        writeln!(out, "channels {} -> {}", channels.old, channels.new)?;
+    }
+    let frequency = clip.map(|clip| clip.frequency);
+    if frequency.changed() {
+        writeln!(
+            out,
+            "frequency {} Hz -> {} Hz",
+            frequency.old, frequency.new
+        )?;
+    }
+    let compression = clip.map(|clip| clip.compression);
+    if compression.changed() {
+        writeln!(
+            out,
+            "compression {} -> {}",
+            compression_name(compression.old),
+            compression_name(compression.new)
+        )?;
+    }
+
+    // the offset into the `.resource` file shifts whenever a clip before it changes
+    let hash = clip.map(Clip::hash);
+    if hash.changed() {
+        writeln!(
+            out,
+            "audio changed ({:016x} -> {:016x})",
+            hash.old, hash.new
+        )?;
+        if cx.unity_extract_audio {
+            push_audio(files, name, "old", clip.old, &mut out)?;
+            push_audio(files, name, "new", clip.new, &mut out)?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Summarizes an added or removed audio clip, and extracts it as `{name}.{version}.wav`, `.ogg` or `.fsb` if enabled.
+pub(super) fn describe<P: TypeTreeProvider>(
+    cx: &Context,
+    clips: &AudioClips<'_, P>,
+    path_id: PathId,
+    name: &str,
+    version: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let clip = clips.get(path_id)?;
+    let mut out = format!(
+        "{}s, {} channels, {} Hz, {}\n",
+        clip.length,
+        clip.channels,
+        clip.frequency,
+        compression_name(clip.compression)
+    );
+    if cx.unity_extract_audio {
+        push_audio(files, name, version, &clip, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Writes PCM clips as WAV, Vorbis clips as Ogg and everything else, e.g. ADPCM, as the original FSB5 bank.
+fn push_audio(
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    name: &str,
+    version: &str,
+    clip: &Clip,
+    out: &mut String,
+) -> Result<()> {
+    if clip.data.is_empty() {
+        return Ok(());
+    }
+
+    let extracted = Fsb5::parse(&clip.data).and_then(|fsb| match fsb.mode {
+        Fsb5::MODE_VORBIS => Ok(Some(("ogg", to_ogg(&clip.data)?))),
+        _ => Ok(fsb.to_wav(&clip.data)?.map(|wav| ("wav", wav))),
+    });
+    match extracted {
+        Ok(Some((extension, audio))) => {
+            files.push((format!("{name}.{version}.{extension}").into(), audio))
+        }
+        Ok(None) => files.push((format!("{name}.{version}.fsb").into(), clip.data.clone())),
+        Err(e) => writeln!(out, "could not extract {version} audio: {e}")?,
+    }
+    Ok(())
+}
+
+/// Rebuilds the Ogg stream of a Vorbis bank. FMOD strips the Vorbis headers and only stores the CRC32
+/// of the setup header, which `fsbex` looks up in the headers of the known FMOD encoder settings.
+fn to_ogg(data: &[u8]) -> Result<Vec<u8>> {
+    let bank = fsbex::Bank::new(data).context("reading Vorbis bank")?;
+    let stream = bank.into_iter().next().context("empty FSB5 bank")?;
+    let mut ogg = Vec::new();
+    stream.write(&mut ogg).context("rebuilding Ogg stream")?;
+    Ok(ogg)
+}
+
+/// See https://docs.unity3d.com/ScriptReference/AudioCompressionFormat.html
+fn compression_name(format: i32) -> String {
+    let name = match format {
+        0 => "PCM",
+        1 => "Vorbis",
+        2 => "ADPCM",
+        3 => "MP3",
+        4 => "VAG",
+        5 => "HEVAG",
+        6 => "XMA",
+        7 => "AAC",
+        8 => "GCADPCM",
+        9 => "ATRAC9",
+        _ => return format!("AudioCompressionFormat({format})"),
+    };
+    name.to_owned()
+}
+
+/// The parts of an FMOD sample bank needed to extract its first sample.
+struct Fsb5 {
+    mode: u32,
+    frequency: u32,
+    channels: u16,
+    data: Range<usize>,
+}
+
+impl Fsb5 {
+    const MODE_PCM8: u32 = 1;
+    const MODE_PCM16: u32 = 2;
+    const MODE_PCMFLOAT: u32 = 5;
+    const MODE_VORBIS: u32 = 15;
+
+    fn parse(data: &[u8]) -> Result<Fsb5> {
+        let u32_at = |offset: usize| -> Result<u32> {
+            let bytes = data
+                .get(offset..offset + 4)
+                .context("truncated FSB5 header")?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        ensure!(data.starts_with(b"FSB5"), "not an FSB5 bank");
+        let version = u32_at(4)?;
+        let num_samples = u32_at(8)?;
+        let sample_headers_size = u32_at(12)? as usize;
+        let name_table_size = u32_at(16)? as usize;
+        let data_size = u32_at(20)? as usize;
+        let mode = u32_at(24)?;
+        ensure!(num_samples > 0, "empty FSB5 bank");
+
+        let header_size = match version {
+            0 => 0x40,
+            1 => 0x3C,
+            _ => bail!("unknown FSB5 version {version}"),
+        };
+
+        let sample = u64::from_le_bytes(
+            data.get(header_size..header_size + 8)
+                .context("truncated FSB5 sample header")?
+                .try_into()
+                .unwrap(),
+        );
+        let bits = |offset: u32, len: u32| (sample >> offset) & ((1 << len) - 1);
+
+        let mut frequency = match bits(1, 4) {
+            0 => 4000,
+            1 => 8000,
+            2 => 11000,
+            3 => 11025,
+            4 => 16000,
+            5 => 22050,
+            6 => 24000,
+            7 => 32000,
+            8 => 44100,
+            9 => 48000,
+            10 => 96000,
+            other => bail!("unknown FSB5 frequency index {other}"),
+        };
+        let mut channels = bits(5, 1) as u16 + 1;
+        let data_offset = bits(6, 28) as usize * 16;
+
+        // extra chunks may override the channels and frequency
+        let mut has_chunk = bits(0, 1) == 1;
+        let mut offset = header_size + 8;
+        while has_chunk {
+            let chunk = u32_at(offset)?;
+            has_chunk = chunk & 1 == 1;
+            let size = ((chunk >> 1) & 0xFF_FFFF) as usize;
+            let kind = chunk >> 25;
+            offset += 4;
+            match kind {
+                1 => channels = (*data.get(offset).context("truncated FSB5 chunk")?).into(),
+                2 => frequency = u32_at(offset)?,
+                _ => {}
+            }
+            offset += size;
+        }
+
+        let data_start = header_size + sample_headers_size + name_table_size;
+        let data_end = (data_start + data_size).min(data.len());
+        let sample_end = if num_samples > 1 {
+            // the next sample's data starts where this one ends
+            let next = u64::from_le_bytes(
+                data.get(offset..offset + 8)
+                    .context("truncated FSB5 sample header")?
+                    .try_into()
+                    .unwrap(),
+            );
+            data_start + ((next >> 6) & ((1 << 28) - 1)) as usize * 16
+        } else {
+            data_end
+        };
+
+        Ok(Fsb5 {
+            mode,
+            frequency,
+            channels,
+            data: data_start + data_offset..sample_end.min(data_end),
+        })
+    }
+
+    /// `None` for compressed samples, which can't be written as WAV.
+    fn to_wav(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (format, bits_per_sample) = match self.mode {
+            Fsb5::MODE_PCM8 => (1u16, 8u16),
+            Fsb5::MODE_PCM16 => (1, 16),
+            Fsb5::MODE_PCMFLOAT => (3, 32),
+            _ => return Ok(None),
+        };
+        let mut samples = data
+            .get(self.data.clone())
+            .context("FSB5 sample data out of bounds")?
+            .to_vec();
+        if self.mode == Fsb5::MODE_PCM8 {
+            // FMOD stores signed 8-bit samples, WAV expects unsigned
+            samples.iter_mut().for_each(|sample| *sample ^= 0x80);
+        }
+
+        let block_align = self.channels * bits_per_sample / 8;
+        let mut wav = Vec::with_capacity(44 + samples.len());
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&format.to_le_bytes());
+        wav.extend_from_slice(&self.channels.to_le_bytes());
+        wav.extend_from_slice(&self.frequency.to_le_bytes());
+        wav.extend_from_slice(&(self.frequency * u32::from(block_align)).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&bits_per_sample.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&samples);
+        Ok(Some(wav))
+    }
+}
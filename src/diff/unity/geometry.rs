@@ -0,0 +1,518 @@
+//! Summarizes changes to 2D collider shapes and meshes instead of diffing their point arrays.
+
+use std::f32::consts::PI;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use rabex::objects::ClassId;
+use serde_json::Value;
+
+use crate::diff::{Context, values};
+use crate::old_new::OldNew;
+
+/// Moved points are listed individually up to this many
+const MAX_MOVED_POINTS: usize = 8;
+/// Segments used to draw circles and the caps of capsules
+const CIRCLE_SEGMENTS: usize = 32;
+
+type Point = (f32, f32);
+
+struct Path {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+/// The paths of a collider in its local space, without its offset.
+struct Shape {
+    paths: Vec<Path>,
+    offset: Point,
+}
+
+pub(super) fn is_collider(class_id: ClassId) -> bool {
+    matches!(
+        class_id,
+        ClassId::PolygonCollider2D
+            | ClassId::EdgeCollider2D
+            | ClassId::BoxCollider2D
+            | ClassId::CircleCollider2D
+            | ClassId::CapsuleCollider2D
+    )
+}
+
+/// Diffs a 2D collider by its shape, and writes an SVG overlay of both shapes if enabled.
+pub(super) fn diff_collider(
+    cx: &Context,
+    class_id: ClassId,
+    value: OldNew<Value>,
+    name: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let class = format!("{class_id:?}");
+    let shape = value.as_ref().map(|value| Shape::new(class_id, value));
+    let mut out = String::new();
+
+    // boxes, circles and capsules are readable as JSON
+    let point_based = matches!(
+        class_id,
+        ClassId::PolygonCollider2D | ClassId::EdgeCollider2D
+    );
+    let mut summarized: &[&str] = &[];
+    if point_based && let (Some(old), Some(new)) = (&shape.old, &shape.new) {
+        out.push_str(&summarize_paths(OldNew::new(
+            &old.paths[..],
+            &new.paths[..],
+        ))?);
+        summarized = &["m_Points"];
+    }
+    out.push_str(&values::diff_json_without(cx, value, summarized, &class)?);
+
+    if cx.unity_collider_svg
+        && !out.is_empty()
+        && let (Some(old), Some(new)) = (&shape.old, &shape.new)
+    {
+        let svg = svg_overlay(OldNew::new(old, new));
+        files.push((format!("{name}.{class}.svg").into(), svg.into_bytes()));
+    }
+
+    Ok(out.trim_end().to_owned())
+}
+
+impl Shape {
+    fn new(class_id: ClassId, value: &Value) -> Option<Shape> {
+        let offset = value.get("m_Offset").and_then(point).unwrap_or((0.0, 0.0));
+        let number = |key: &str| value.get(key).and_then(Value::as_f64).map(|n| n as f32);
+
+        let paths = match class_id {
+            ClassId::PolygonCollider2D => value
+                .pointer("/m_Points/m_Paths")?
+                .as_array()?
+                .iter()
+                .map(|path| Path {
+                    points: points(path),
+                    closed: true,
+                })
+                .collect(),
+            ClassId::EdgeCollider2D => vec![Path {
+                points: points(value.get("m_Points")?),
+                closed: false,
+            }],
+            ClassId::BoxCollider2D => {
+                let (w, h) = point(value.get("m_Size")?)?;
+                let (x, y) = (w / 2.0, h / 2.0);
+                vec![Path {
+                    points: vec![(-x, -y), (x, -y), (x, y), (-x, y)],
+                    closed: true,
+                }]
+            }
+            ClassId::CircleCollider2D => vec![Path {
+                points: arc(
+                    (0.0, 0.0),
+                    number("m_Radius")?,
+                    0.0,
+                    2.0 * PI,
+                    CIRCLE_SEGMENTS,
+                ),
+                closed: true,
+            }],
+            ClassId::CapsuleCollider2D => {
+                let (w, h) = point(value.get("m_Size")?)?;
+                let vertical = number("m_Direction")? == 0.0;
+                let (radius, length) = if vertical { (w, h) } else { (h, w) };
+                let radius = radius / 2.0;
+                let half = (length / 2.0 - radius).max(0.0);
+
+                let segments = CIRCLE_SEGMENTS / 2;
+                let mut points = Vec::new();
+                if vertical {
+                    points.extend(arc((0.0, half), radius, 0.0, PI, segments));
+                    points.extend(arc((0.0, -half), radius, PI, PI, segments));
+                } else {
+                    points.extend(arc((half, 0.0), radius, -PI / 2.0, PI, segments));
+                    points.extend(arc((-half, 0.0), radius, PI / 2.0, PI, segments));
+                }
+                vec![Path {
+                    points,
+                    closed: true,
+                }]
+            }
+            _ => return None,
+        };
+        Some(Shape { paths, offset })
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        bounds(&self.paths, self.offset)
+    }
+}
+
+/// The minimum and maximum corner of all points.
+fn bounds(paths: &[Path], (dx, dy): Point) -> Option<(Point, Point)> {
+    paths
+        .iter()
+        .flat_map(|path| &path.points)
+        .map(|&(x, y)| (x + dx, y + dy))
+        .fold(None, |bounds, (x, y)| {
+            let ((min_x, min_y), (max_x, max_y)) = bounds.unwrap_or(((x, y), (x, y)));
+            Some(((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))))
+        })
+}
+
+/// Points on a circle, starting at `start` radians and going counterclockwise.
+fn arc(center: Point, radius: f32, start: f32, sweep: f32, segments: usize) -> Vec<Point> {
+    let closed = sweep >= 2.0 * PI;
+    let count = if closed { segments } else { segments + 1 };
+    (0..count)
+        .map(|i| {
+            let angle = start + sweep * i as f32 / segments as f32;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+fn point(value: &Value) -> Option<Point> {
+    let x = value.get("x")?.as_f64()?;
+    let y = value.get("y")?.as_f64()?;
+    Some((x as f32, y as f32))
+}
+
+fn points(value: &Value) -> Vec<Point> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(point)
+        .collect()
+}
+
+fn format_point((x, y): Point) -> String {
+    format!(
+        "({}, {})",
+        values::number(x.into()),
+        values::number(y.into())
+    )
+}
+
+fn format_bounds(((min_x, min_y), (max_x, max_y)): (Point, Point)) -> String {
+    format!(
+        "({}, {}, {}×{})",
+        values::number(min_x.into()),
+        values::number(min_y.into()),
+        values::number((max_x - min_x).into()),
+        values::number((max_y - min_y).into())
+    )
+}
+
+/// Shoelace area of the closed paths, and length of the open ones.
+fn area_and_length(paths: &[Path]) -> (f32, f32) {
+    let mut area = 0.0;
+    let mut length = 0.0;
+    for path in paths {
+        let segments = path.points.iter().zip(path.points.iter().skip(1));
+        if path.closed {
+            let closing = path.points.last().zip(path.points.first());
+            let signed: f32 = segments
+                .chain(closing)
+                .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+                .sum();
+            area += signed.abs() / 2.0;
+        } else {
+            length += segments
+                .map(|(a, b)| (b.0 - a.0).hypot(b.1 - a.1))
+                .sum::<f32>();
+        }
+    }
+    (area, length)
+}
+
+fn summarize_paths(paths: OldNew<&[Path]>) -> Result<String> {
+    let mut out = String::new();
+
+    let path_count = paths.map(|paths| paths.len());
+    if path_count.changed() {
+        writeln!(out, "paths {} -> {}", path_count.old, path_count.new)?;
+    }
+    let point_count = paths.map(|paths| paths.iter().map(|path| path.points.len()).sum::<usize>());
+    if point_count.changed() {
+        writeln!(out, "points {} -> {}", point_count.old, point_count.new)?;
+    }
+
+    let bounds = paths.map(|paths| bounds(paths, (0.0, 0.0)));
+    if bounds.changed() {
+        let format = |bounds: Option<_>| bounds.map_or_else(|| "empty".to_owned(), format_bounds);
+        writeln!(
+            out,
+            "bounds {} -> {}",
+            format(bounds.old),
+            format(bounds.new)
+        )?;
+    }
+
+    let measures = paths.map(area_and_length);
+    let (area, length) = (measures.map(|m| m.0), measures.map(|m| m.1));
+    if area.changed() {
+        writeln!(
+            out,
+            "area {} -> {}",
+            values::number(area.old.into()),
+            values::number(area.new.into())
+        )?;
+    }
+    if length.changed() {
+        writeln!(
+            out,
+            "length {} -> {}",
+            values::number(length.old.into()),
+            values::number(length.new.into())
+        )?;
+    }
+
+    // individual points are only comparable if no points were inserted
+    let same_layout = path_count.old == path_count.new
+        && paths
+            .old
+            .iter()
+            .zip(paths.new)
+            .all(|(old, new)| old.points.len() == new.points.len());
+    if same_layout {
+        let moved: Vec<_> = paths
+            .old
+            .iter()
+            .zip(paths.new)
+            .enumerate()
+            .flat_map(|(i, (old, new))| {
+                old.points
+                    .iter()
+                    .zip(&new.points)
+                    .enumerate()
+                    .filter(|(_, (old, new))| old != new)
+                    .map(move |(j, (&old, &new))| (i, j, old, new))
+            })
+            .collect();
+
+        if moved.len() > MAX_MOVED_POINTS {
+            writeln!(out, "{} of {} points moved", moved.len(), point_count.new)?;
+        } else {
+            for (i, j, old, new) in moved {
+                let path = if path_count.new > 1 {
+                    format!("path {i} ")
+                } else {
+                    String::new()
+                };
+                writeln!(
+                    out,
+                    "{path}point {j} {} -> {}",
+                    format_point(old),
+                    format_point(new)
+                )?;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Draws the old shape in red and the new one in green on top of each other.
+fn svg_overlay(shape: OldNew<&Shape>) -> String {
+    const SIZE: f32 = 512.0;
+
+    let bounds = [shape.old.bounds(), shape.new.bounds()]
+        .into_iter()
+        .flatten()
+        .reduce(|(a_min, a_max), (b_min, b_max)| {
+            (
+                (a_min.0.min(b_min.0), a_min.1.min(b_min.1)),
+                (a_max.0.max(b_max.0), a_max.1.max(b_max.1)),
+            )
+        })
+        .unwrap_or(((0.0, 0.0), (1.0, 1.0)));
+    let ((min_x, min_y), (max_x, max_y)) = bounds;
+    let margin = (max_x - min_x).max(max_y - min_y).max(0.01) * 0.05;
+    let (width, height) = (max_x - min_x + 2.0 * margin, max_y - min_y + 2.0 * margin);
+    let scale = SIZE / width.max(height);
+
+    let mut svg = String::new();
+    // unity's y axis points up, flipping it in the transform keeps the viewBox positive
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {width} {height}">"#,
+        (width * scale).round(),
+        (height * scale).round(),
+        min_x - margin,
+        -max_y - margin,
+    );
+    let _ = writeln!(svg, r#"<g transform="scale(1,-1)">"#);
+    for (shape, color) in [(shape.old, "#e5484d"), (shape.new, "#30a46c")] {
+        let (dx, dy) = shape.offset;
+        let mut d = String::new();
+        for path in &shape.paths {
+            for (i, (x, y)) in path.points.iter().enumerate() {
+                let command = if i == 0 { 'M' } else { 'L' };
+                let _ = write!(d, "{command}{} {} ", x + dx, y + dy);
+            }
+            if path.closed {
+                d.push_str("Z ");
+            }
+        }
+        let _ = writeln!(
+            svg,
+            r#"<path d="{}" fill="{color}" fill-opacity="0.2" stroke="{color}" stroke-width="2" vector-effect="non-scaling-stroke"/>"#,
+            d.trim_end()
+        );
+    }
+    let _ = writeln!(svg, "</g>");
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+/// Diffs a mesh by its vertex, submesh and triangle counts and how many vertices moved,
+/// and the remaining fields as JSON.
+pub(super) fn diff_mesh(cx: &Context, value: OldNew<Value>, class: &str) -> Result<String> {
+    let mesh = value.as_ref().map(MeshSummary::new);
+    let mut out = String::new();
+
+    let vertices = mesh.as_ref().map(|mesh| mesh.vertex_count);
+    if vertices.changed() {
+        writeln!(out, "vertices {} -> {}", vertices.old, vertices.new)?;
+    }
+    let submeshes = mesh.as_ref().map(|mesh| mesh.submeshes);
+    if submeshes.changed() {
+        writeln!(out, "submeshes {} -> {}", submeshes.old, submeshes.new)?;
+    }
+    let triangles = mesh.as_ref().map(|mesh| mesh.triangles);
+    if triangles.changed() {
+        writeln!(out, "triangles {} -> {}", triangles.old, triangles.new)?;
+    }
+
+    let positions = mesh.as_ref().map(|mesh| mesh.positions.as_deref());
+    match (positions.old, positions.new) {
+        (Some(old), Some(new)) if old.len() == new.len() => {
+            let moved = old.iter().zip(new).filter(|(old, new)| old != new).count();
+            if moved > 0 {
+                writeln!(out, "{moved} of {} vertices moved", new.len())?;
+            }
+        }
+        _ => {}
+    }
+    let vertex_data = value
+        .as_ref()
+        .map(|value| value.pointer("/m_VertexData/_typelessdata"));
+    if vertex_data.changed() && !positions.changed() && vertices.old == vertices.new {
+        writeln!(out, "vertex attributes changed")?;
+    }
+    let indices = value.as_ref().map(|value| value.get("m_IndexBuffer"));
+    if indices.changed() && !triangles.changed() {
+        writeln!(out, "indices changed")?;
+    }
+    let compressed = value.as_ref().map(|value| value.get("m_CompressedMesh"));
+    if compressed.changed() {
+        writeln!(out, "compressed mesh data changed")?;
+    }
+
+    out.push_str(&values::diff_json_without(
+        cx,
+        value,
+        &[
+            "m_VertexData",
+            "m_IndexBuffer",
+            "m_CompressedMesh",
+            "m_BakedConvexCollisionMesh",
+            "m_BakedTriangleCollisionMesh",
+        ],
+        class,
+    )?);
+
+    Ok(out.trim_end().to_owned())
+}
+
+struct MeshSummary {
+    vertex_count: u64,
+    submeshes: usize,
+    triangles: u64,
+    /// `None` if the positions aren't stored as floats
+    positions: Option<Vec<[f32; 3]>>,
+}
+
+impl MeshSummary {
+    fn new(value: &Value) -> MeshSummary {
+        let submeshes = value
+            .get("m_SubMeshes")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        let triangles = submeshes
+            .iter()
+            // other topologies are lines and points
+            .filter(|submesh| submesh.get("topology").and_then(Value::as_u64) == Some(0))
+            .filter_map(|submesh| submesh.get("indexCount").and_then(Value::as_u64))
+            .map(|count| count / 3)
+            .sum();
+
+        let vertex_data = value.get("m_VertexData");
+        MeshSummary {
+            vertex_count: vertex_data
+                .and_then(|data| data.get("m_VertexCount"))
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+            submeshes: submeshes.len(),
+            triangles,
+            positions: vertex_data.and_then(positions),
+        }
+    }
+}
+
+/// Decodes the position channel of the interleaved vertex streams.
+fn positions(vertex_data: &Value) -> Option<Vec<[f32; 3]>> {
+    let count = vertex_data.get("m_VertexCount")?.as_u64()? as usize;
+    let channels = vertex_data.get("m_Channels")?.as_array()?;
+    let data: Vec<u8> = vertex_data
+        .get("_typelessdata")?
+        .as_array()?
+        .iter()
+        .filter_map(|byte| Some(byte.as_u64()? as u8))
+        .collect();
+
+    let field = |channel: &Value, key: &str| channel.get(key).and_then(Value::as_u64).unwrap_or(0);
+    // see `VertexFormat`, sizes in bytes
+    let format_size = |format: u64| match format {
+        0 | 10 | 11 => 4,
+        1 | 4 | 5 | 8 | 9 => 2,
+        _ => 1,
+    };
+
+    let streams = channels.iter().map(|c| field(c, "stream")).max()?;
+    let mut stream_offsets = Vec::new();
+    let mut strides = Vec::new();
+    let mut offset = 0;
+    for stream in 0..=streams {
+        let stride: u64 = channels
+            .iter()
+            .filter(|c| field(c, "stream") == stream)
+            .map(|c| format_size(field(c, "format")) * (field(c, "dimension") & 0xF))
+            .sum();
+        stream_offsets.push(offset);
+        strides.push(stride as usize);
+        // streams are aligned to 16 bytes
+        offset = (offset + stride as usize * count).next_multiple_of(16);
+    }
+
+    let position = channels.first()?;
+    if field(position, "format") != 0 || field(position, "dimension") & 0xF < 3 {
+        return None;
+    }
+    let stream = field(position, "stream") as usize;
+    let start = stream_offsets[stream] + field(position, "offset") as usize;
+
+    (0..count)
+        .map(|i| {
+            let vertex = start + i * strides[stream];
+            let float = |j: usize| -> Option<f32> {
+                let bytes = data.get(vertex + j * 4..vertex + j * 4 + 4)?;
+                Some(f32::from_le_bytes(bytes.try_into().ok()?))
+            };
+            Some([float(0)?, float(1)?, float(2)?])
+        })
+        .collect()
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, KeyInit};
+use anyhow::{Context as _, Result, ensure};
+use base64::Engine;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use regex::Regex;
+
+use crate::old_new::OldNew;
+
+use super::assets::AssetKey;
+use super::text_asset;
+
+/// Team Cherry's language sheets are TextAssets named like `EN_Belltown`
+static SHEET_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^([A-Z]{2}(?:_[A-Z]{2})?)_(.+)$").unwrap());
+static ENTRY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<entry name="([^"]*)">([^<]*)</entry>"#).unwrap());
+
+/// The key used to encrypt the language sheets, shared with Hollow Knight
+const KEY: &[u8; 32] = b"UKu52ePUBwetZ9wNX88o54dnfKRu0T1l";
+
+/// A name with the index disambiguating assets of the same name, like in [`AssetKey`]
+type Indexed = (String, usize);
+
+/// The language sheets of one version of a serialized file.
+#[derive(Default)]
+pub(super) struct Sheets {
+    /// sheet -> language -> key -> text
+    sheets: BTreeMap<Indexed, BTreeMap<String, BTreeMap<String, String>>>,
+    /// The TextAssets that were decoded, which don't need to be diffed on their own
+    assets: BTreeSet<Indexed>,
+    /// The TextAssets that are named like a sheet but couldn't be decoded, with the error
+    failed: BTreeMap<Indexed, String>,
+}
+
+impl Sheets {
+    pub fn read<P: TypeTreeProvider>(
+        file: &SerializedFileHandle<'_, GameFiles, P>,
+        assets: &BTreeMap<AssetKey, PathId>,
+    ) -> Sheets {
+        let mut sheets = Sheets::default();
+        for (key, &path_id) in assets {
+            if key.class_id != ClassId::TextAsset {
+                continue;
+            }
+            let Some(captures) = SHEET_NAME.captures(&key.name) else {
+                continue;
+            };
+            // TextAssets that only look like language sheets are diffed as text
+            let entries = match text_asset::read(file, path_id).and_then(|data| decode(&data)) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    sheets
+                        .failed
+                        .insert((key.name.clone(), key.index), format!("{e:#}"));
+                    continue;
+                }
+            };
+
+            sheets
+                .sheets
+                .entry((captures[2].to_owned(), key.index))
+                .or_default()
+                .insert(captures[1].to_owned(), entries);
+            sheets.assets.insert((key.name.clone(), key.index));
+        }
+        sheets
+    }
+
+    /// Whether the asset was decoded as a language sheet.
+    /// Sheets that only decode in one version are reported by [`diff_sheets`] and not diffed at all.
+    pub fn contains(&self, key: &AssetKey) -> bool {
+        key.class_id == ClassId::TextAsset && self.assets.contains(&(key.name.clone(), key.index))
+    }
+}
+
+/// Decrypts a language sheet and parses its XML entries.
+fn decode(data: &[u8]) -> Result<BTreeMap<String, String>> {
+    let xml = if data.trim_ascii_start().starts_with(b"<") {
+        String::from_utf8(data.to_vec())?
+    } else {
+        let mut data = base64::engine::general_purpose::STANDARD.decode(data.trim_ascii())?;
+        ensure!(data.len() % 16 == 0, "not AES encrypted");
+
+        let cipher = aes::Aes256::new(GenericArray::from_slice(KEY));
+        for block in data.chunks_exact_mut(16) {
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+        // PKCS#7 padding
+        let padding = *data.last().context("empty sheet")? as usize;
+        ensure!((1..=16).contains(&padding), "invalid padding");
+        data.truncate(data.len() - padding);
+
+        String::from_utf8(data)?
+    };
+
+    let entries: BTreeMap<_, _> = ENTRY
+        .captures_iter(&xml)
+        .map(|entry| (unescape(&entry[1]), unescape(&entry[2])))
+        .collect();
+    ensure!(!entries.is_empty(), "no entries");
+    Ok(entries)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Writes a table of the changed keys of every sheet per language, with the old and new text.
+pub(super) fn diff_sheets(
+    sheets: OldNew<&Sheets>,
+    out: &mut String,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let names: BTreeSet<&Indexed> = sheets
+        .old
+        .sheets
+        .keys()
+        .chain(sheets.new.sheets.keys())
+        .collect();
+    let empty = BTreeMap::new();
+    let no_entries = BTreeMap::new();
+
+    for name in names {
+        let sheet = sheets.map(|sheets| sheets.sheets.get(name).unwrap_or(&empty));
+        let languages: BTreeSet<&String> = sheet.old.keys().chain(sheet.new.keys()).collect();
+
+        let mut section = String::new();
+        for language in languages {
+            let asset = (format!("{language}_{}", name.0), name.1);
+            let failed = [("old", sheets.old), ("new", sheets.new)]
+                .into_iter()
+                .find_map(|(version, sheets)| Some((version, sheets.failed.get(&asset)?)));
+            if let Some((version, error)) = failed {
+                warnings.push(format!(
+                    "Skipping language sheet {}, the {version} version could not be decoded: {error}",
+                    label(&asset)
+                ));
+                continue;
+            }
+
+            let entries = sheet.map(|sheet| sheet.get(language).unwrap_or(&no_entries));
+            let table = language_table(entries)?;
+            if !table.is_empty() {
+                writeln!(section, "{language}")?;
+                section.push_str(&table);
+            }
+        }
+
+        if !section.is_empty() {
+            writeln!(out, "--- Changed localization sheet {} ---", label(name))?;
+            out.push_str(&section);
+        }
+    }
+
+    Ok(())
+}
+
+fn label((name, index): &Indexed) -> String {
+    match index {
+        0 => format!("'{name}'"),
+        index => format!("'{name}' #{index}"),
+    }
+}
+
+/// The changed keys of one language, with the key, old and new text aligned in columns.
+fn language_table(entries: OldNew<&BTreeMap<String, String>>) -> Result<String> {
+    let changes = entries.changes(|entries| entries.keys());
+    let mut rows: Vec<(&str, [String; 3])> = Vec::new();
+    for key in changes.removed {
+        let row = [
+            format!("- {key}"),
+            oneline(&entries.old[key]),
+            String::new(),
+        ];
+        rows.push((key, row));
+    }
+    for key in changes.added {
+        let row = [
+            format!("+ {key}"),
+            String::new(),
+            oneline(&entries.new[key]),
+        ];
+        rows.push((key, row));
+    }
+    for key in changes.same {
+        let text = entries.map(|entries| &entries[key]);
+        if text.changed() {
+            rows.push((
+                key,
+                [format!("~ {key}"), oneline(text.old), oneline(text.new)],
+            ));
+        }
+    }
+    if rows.is_empty() {
+        return Ok(String::new());
+    }
+    rows.sort_by_key(|&(key, _)| key);
+
+    let width = |column: usize| {
+        rows.iter()
+            .map(|(_, row)| row[column].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (key_width, old_width) = (width(0).max(5), width(1).max(3));
+    let mut table = format!("  {:<key_width$} | {:<old_width$} | new\n", "  key", "old");
+    for (_, [key, old, new]) in rows {
+        let line = format!("  {key:<key_width$} | {old:<old_width$} | {new}");
+        writeln!(table, "{}", line.trim_end())?;
+    }
+    Ok(table)
+}
+
+fn oneline(text: &str) -> String {
+    format!("{text:?}")
+}
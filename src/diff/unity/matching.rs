@@ -0,0 +1,218 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::{ObjectRefHandle, SerializedFileHandle};
+use rabex_env::unity::types::{GameObject, Transform};
+
+use crate::old_new::OldNew;
+
+use super::{ComponentKey, component_key};
+
+/// What a GameObject is compared by when matching it to its counterpart in the other version.
+pub(super) struct Signature {
+    path_id: PathId,
+    name: String,
+    components: BTreeSet<ComponentKey>,
+    children: usize,
+    position: [f32; 3],
+    rotation: [f32; 4],
+    scale: [f32; 3],
+}
+
+impl Signature {
+    pub fn new<P: TypeTreeProvider>(
+        file: &SerializedFileHandle<'_, GameFiles, P>,
+        path_id: PathId,
+        (transform, go): (&Transform, &GameObject),
+    ) -> Result<Signature> {
+        let mut components = BTreeSet::new();
+        for component in go.components(file.file, &file.env.tpk) {
+            let component = ObjectRefHandle::new(component?, file.reborrow());
+            components.insert(component_key(&component)?);
+        }
+
+        Ok(Signature {
+            path_id,
+            name: go.m_Name.clone(),
+            components,
+            children: transform.m_Children.len(),
+            position: [
+                transform.m_LocalPosition.x,
+                transform.m_LocalPosition.y,
+                transform.m_LocalPosition.z,
+            ],
+            rotation: [
+                transform.m_LocalRotation.x,
+                transform.m_LocalRotation.y,
+                transform.m_LocalRotation.z,
+                transform.m_LocalRotation.w,
+            ],
+            scale: [
+                transform.m_LocalScale.x,
+                transform.m_LocalScale.y,
+                transform.m_LocalScale.z,
+            ],
+        })
+    }
+
+    /// How close the local transforms are, from 0 to 1 for identical ones.
+    fn transform_similarity(&self, other: &Signature) -> f32 {
+        let closeness = |a: &[f32], b: &[f32]| {
+            let distance = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            1.0 / (1.0 + distance)
+        };
+        // `q` and `-q` are the same rotation
+        let dot: f32 = self
+            .rotation
+            .iter()
+            .zip(&other.rotation)
+            .map(|(a, b)| a * b)
+            .sum();
+        let rotation = dot.abs().min(1.0);
+
+        (closeness(&self.position, &other.position)
+            + rotation
+            + closeness(&self.scale, &other.scale))
+            / 3.0
+    }
+
+    fn similarity(&self, other: &Signature) -> f32 {
+        let union = self.components.union(&other.components).count();
+        if union == 0 {
+            return 1.0;
+        }
+        let intersection = self.components.intersection(&other.components).count();
+        intersection as f32 / union as f32
+    }
+
+    /// Scores how likely `self` and `new` are the same object, or `None` if they are clearly different.
+    fn score(&self, new: &Signature, distance: usize) -> Option<f32> {
+        let same_name = self.name == new.name;
+        let same_path_id = self.path_id == new.path_id;
+        let similarity = self.similarity(new);
+        let same_children = self.children == new.children;
+        let transform_similarity = self.transform_similarity(new);
+        let same_transform = transform_similarity == 1.0;
+
+        let plausible = same_name
+            || (same_path_id && similarity >= 0.5)
+            || (similarity == 1.0
+                && self.components.len() > 1
+                && (same_children || same_transform));
+        if !plausible {
+            return None;
+        }
+
+        let mut score = 3.0 * similarity + 2.0 * transform_similarity;
+        if same_name {
+            score += 4.0;
+        }
+        if same_path_id {
+            score += 3.0;
+        }
+        if same_children {
+            score += 1.0;
+        }
+        // prefer keeping the sibling order when everything else is equal
+        score -= 0.01 * distance as f32;
+        Some(score)
+    }
+
+    /// Like [`Signature::score`], but for objects that changed their parent, where only
+    /// a surviving path ID or an identical name and component set is convincing.
+    fn score_moved(&self, new: &Signature) -> Option<f32> {
+        let same_path_id = self.path_id == new.path_id;
+        let identical = self.name == new.name && self.components == new.components;
+        (same_path_id || identical).then(|| {
+            // the local transform usually changes with the parent, so it only breaks ties
+            let mut score = 3.0 * self.similarity(new) + self.transform_similarity(new);
+            if same_path_id {
+                score += 3.0;
+            }
+            if identical {
+                score += 4.0;
+            }
+            score
+        })
+    }
+}
+
+/// Pairs up old and new siblings, returning the index of the old counterpart for every new sibling.
+pub(super) fn match_siblings(old: &[Signature], new: &[Signature]) -> Vec<Option<usize>> {
+    assign(old, new, |(i, old), (j, new)| old.score(new, i.abs_diff(j)))
+}
+
+/// Pairs up objects that disappeared from one parent with objects that appeared under another.
+pub(super) fn match_moved(old: &[Signature], new: &[Signature]) -> Vec<Option<usize>> {
+    assign(old, new, |(_, old), (_, new)| old.score_moved(new))
+}
+
+/// Greedily assigns the highest scoring pairs first.
+fn assign(
+    old: &[Signature],
+    new: &[Signature],
+    score: impl Fn((usize, &Signature), (usize, &Signature)) -> Option<f32>,
+) -> Vec<Option<usize>> {
+    let mut candidates = Vec::new();
+    for (i, old) in old.iter().enumerate() {
+        for (j, new) in new.iter().enumerate() {
+            if let Some(score) = score((i, old), (j, new)) {
+                candidates.push((score, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut old_used = vec![false; old.len()];
+    let mut matches = vec![None; new.len()];
+    for (_, i, j) in candidates {
+        if !old_used[i] && matches[j].is_none() {
+            old_used[i] = true;
+            matches[j] = Some(i);
+        }
+    }
+    matches
+}
+
+/// Pairs up the indices of equal items by their longest common subsequence.
+pub(super) fn align(items: OldNew<Vec<&str>>) -> Vec<(Option<usize>, Option<usize>)> {
+    let (old, new) = (&items.old, &items.new);
+    // lengths[i][j] is the LCS length of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            pairs.push((Some(i), None));
+            i += 1;
+        } else {
+            pairs.push((None, Some(j)));
+            j += 1;
+        }
+    }
+    pairs.extend((i..old.len()).map(|i| (Some(i), None)));
+    pairs.extend((j..new.len()).map(|j| (None, Some(j))));
+    pairs
+}
@@ -0,0 +1,356 @@
+//! Diffs materials by their properties and keywords, and shaders by their
+//! properties, keywords, subshaders and passes, without the compiled programs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::diff::values::{self, array, string};
+use crate::old_new::OldNew;
+
+/// The property lists of `m_SavedProperties`, and how their entries are called in the diff
+const MATERIAL_PROPERTIES: &[(&str, &str)] = &[
+    ("m_TexEnvs", "texture"),
+    ("m_Ints", "int"),
+    ("m_Floats", "float"),
+    ("m_Colors", "color"),
+];
+
+/// The shader programs of a pass, by stage
+const PROGRAMS: &[(&str, &str)] = &[
+    ("progVertex", "vertex"),
+    ("progFragment", "fragment"),
+    ("progGeometry", "geometry"),
+    ("progHull", "hull"),
+    ("progDomain", "domain"),
+    ("progRayTracing", "ray tracing"),
+];
+
+/// Diffs the shader, keywords and properties of a material by name,
+/// and the remaining fields as JSON.
+pub(super) fn diff_material(cx: &Context, value: OldNew<Value>, class: &str) -> Result<String> {
+    let mut out = String::new();
+
+    let shader = value
+        .as_ref()
+        .map(|value| value.get("m_Shader").map(values::render_any));
+    if shader.changed() {
+        let format = |shader: Option<String>| shader.unwrap_or_else(|| "none".to_owned());
+        writeln!(
+            out,
+            "shader {} -> {}",
+            format(shader.old),
+            format(shader.new)
+        )?;
+    }
+
+    let keywords = value.as_ref().map(material_keywords);
+    values::diff_set(&mut out, "keyword", keywords.as_ref())?;
+
+    for &(list, kind) in MATERIAL_PROPERTIES {
+        let properties = value.as_ref().map(|value| {
+            named(
+                value,
+                &format!("/m_SavedProperties/{list}"),
+                |property| match kind {
+                    "texture" => texture_property(property),
+                    _ => values::render_any(property),
+                },
+            )
+        });
+        diff_named(&mut out, kind, properties.as_ref())?;
+    }
+    let tags = value
+        .as_ref()
+        .map(|value| named(value, "stringTagMap", values::render_any));
+    diff_named(&mut out, "tag", tags.as_ref())?;
+
+    out.push_str(&values::diff_json_without(
+        cx,
+        value,
+        &[
+            "m_Shader",
+            "m_ShaderKeywords",
+            "m_ValidKeywords",
+            "m_InvalidKeywords",
+            "m_SavedProperties",
+            "stringTagMap",
+        ],
+        class,
+    )?);
+
+    Ok(out.trim_end().to_owned())
+}
+
+/// Enabled keywords. Before Unity 2021.2 they were stored space separated in `m_ShaderKeywords`.
+fn material_keywords(value: &Value) -> BTreeSet<String> {
+    let mut keywords: BTreeSet<String> = ["m_ValidKeywords", "m_InvalidKeywords"]
+        .into_iter()
+        .filter_map(|key| value.get(key)?.as_array())
+        .flatten()
+        .filter_map(|keyword| Some(keyword.as_str()?.to_owned()))
+        .collect();
+    if let Some(legacy) = value.get("m_ShaderKeywords").and_then(Value::as_str) {
+        keywords.extend(legacy.split_whitespace().map(str::to_owned));
+    }
+    keywords
+}
+
+fn texture_property(property: &Value) -> String {
+    let texture = property
+        .get("m_Texture")
+        .map_or_else(|| "none".to_owned(), values::render_any);
+    let texture = if texture == "null" {
+        "none".to_owned()
+    } else {
+        texture
+    };
+    let scale = property.get("m_Scale").map(values::render_any);
+    let offset = property.get("m_Offset").map(values::render_any);
+    match (scale, offset) {
+        (Some(scale), Some(offset)) => format!("{texture} scale {scale} offset {offset}"),
+        _ => texture,
+    }
+}
+
+/// A serialized map from names to values, with the values rendered
+fn named(value: &Value, key: &str, render: impl Fn(&Value) -> String) -> BTreeMap<String, String> {
+    values::pairs(value, key)
+        .filter_map(|(name, value)| Some((name.as_str()?.to_owned(), render(value))))
+        .collect()
+}
+
+fn diff_named(
+    out: &mut String,
+    kind: &str,
+    entries: OldNew<&BTreeMap<String, String>>,
+) -> Result<()> {
+    let changes = entries.changes(|entries| entries.keys());
+    for name in changes.removed {
+        writeln!(out, "{kind} '{name}' removed ({})", entries.old[name])?;
+    }
+    for name in changes.added {
+        writeln!(out, "{kind} '{name}' added ({})", entries.new[name])?;
+    }
+    for name in changes.same {
+        let value = entries.map(|entries| &entries[name]);
+        if value.changed() {
+            writeln!(out, "{kind} '{name}' {} -> {}", value.old, value.new)?;
+        }
+    }
+    Ok(())
+}
+
+/// Diffs the parsed form of a shader, and only reports whether the compiled programs changed.
+pub(super) fn diff_shader(cx: &Context, value: OldNew<Value>, class: &str) -> Result<String> {
+    let mut out = String::new();
+    let empty = Value::Null;
+    let parsed = value
+        .as_ref()
+        .map(|value| value.get("m_ParsedForm").unwrap_or(&empty));
+
+    for (key, label) in [
+        ("m_Name", "name"),
+        ("m_FallbackName", "fallback"),
+        ("m_CustomEditorName", "custom editor"),
+    ] {
+        let field = parsed.map(|parsed| string(parsed, key));
+        if field.changed() {
+            writeln!(out, "{label} '{}' -> '{}'", field.old, field.new)?;
+        }
+    }
+
+    let properties = parsed.map(|parsed| {
+        array(parsed, "/m_PropInfo/m_Props")
+            .iter()
+            .map(|prop| (string(prop, "m_Name").to_owned(), shader_property(prop)))
+            .collect::<BTreeMap<_, _>>()
+    });
+    diff_named(&mut out, "property", properties.as_ref())?;
+
+    let keywords = parsed.map(|parsed| {
+        array(parsed, "m_KeywordNames")
+            .iter()
+            .filter_map(|keyword| Some(keyword.as_str()?.to_owned()))
+            .collect::<BTreeSet<_>>()
+    });
+    values::diff_set(&mut out, "keyword", keywords.as_ref())?;
+
+    let subshaders = parsed.map(|parsed| array(parsed, "m_SubShaders"));
+    if subshaders.old.len() != subshaders.new.len() {
+        writeln!(
+            out,
+            "subshaders {} -> {}",
+            subshaders.old.len(),
+            subshaders.new.len()
+        )?;
+    }
+    for (i, (old, new)) in subshaders.old.iter().zip(subshaders.new).enumerate() {
+        diff_subshader(cx, &mut out, class, i, OldNew::new(old, new))?;
+    }
+
+    let blob = value.as_ref().map(|value| value.get("compressedBlob"));
+    if blob.changed() {
+        let len = |blob: Option<&Value>| blob.and_then(Value::as_array).map_or(0, Vec::len);
+        writeln!(
+            out,
+            "compiled programs changed ({} -> {} bytes)",
+            len(blob.old),
+            len(blob.new)
+        )?;
+    }
+
+    out.push_str(&values::diff_json_without(
+        cx,
+        value,
+        &[
+            "m_ParsedForm",
+            "compressedBlob",
+            "offsets",
+            "compressedLengths",
+            "decompressedLengths",
+            "stageCounts",
+        ],
+        class,
+    )?);
+
+    Ok(out.trim_end().to_owned())
+}
+
+fn shader_property(prop: &Value) -> String {
+    let attributes: Vec<_> = array(prop, "m_Attributes")
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|attribute| format!("[{attribute}]"))
+        .collect();
+    let default_texture = prop
+        .pointer("/m_DefTexture/m_DefaultName")
+        .and_then(Value::as_str)
+        .filter(|name| !name.is_empty());
+    let defaults: Vec<_> = [
+        "m_DefValue[0]",
+        "m_DefValue[1]",
+        "m_DefValue[2]",
+        "m_DefValue[3]",
+    ]
+    .into_iter()
+    .filter_map(|key| prop.get(key))
+    .map(values::render_any)
+    .collect();
+    // see `ShaderPropertyType`
+    let kind = match prop.get("m_Type").and_then(Value::as_u64) {
+        Some(0) => "Color".to_owned(),
+        Some(1) => "Vector".to_owned(),
+        Some(2) => "Float".to_owned(),
+        Some(3) => "Range".to_owned(),
+        Some(4) => "Texture".to_owned(),
+        Some(5) => "Int".to_owned(),
+        other => format!("{other:?}"),
+    };
+
+    let mut rendered = format!(
+        "{}{kind} \"{}\"",
+        attributes.join(""),
+        string(prop, "m_Description"),
+    );
+    match default_texture {
+        Some(texture) => write!(rendered, " default {texture}").unwrap(),
+        None => write!(rendered, " default ({})", defaults.join(", ")).unwrap(),
+    }
+    rendered
+}
+
+fn diff_subshader(
+    cx: &Context,
+    out: &mut String,
+    class: &str,
+    index: usize,
+    subshader: OldNew<&Value>,
+) -> Result<()> {
+    let prefix = format!("subshader {index}");
+
+    let lod = subshader.map(|subshader| subshader.get("m_LOD"));
+    if lod.changed() {
+        let format = |lod: Option<&Value>| lod.map_or_else(String::new, values::render_any);
+        writeln!(
+            out,
+            "{prefix} LOD {} -> {}",
+            format(lod.old),
+            format(lod.new)
+        )?;
+    }
+    let tags = subshader.map(|subshader| named(subshader, "/m_Tags/tags", values::render_any));
+    diff_named(out, &format!("{prefix} tag"), tags.as_ref())?;
+
+    // passes are matched by name, unnamed ones by their index
+    let passes = subshader.map(|subshader| {
+        array(subshader, "m_Passes")
+            .iter()
+            .enumerate()
+            .map(|(i, pass)| {
+                let name = pass
+                    .pointer("/m_State/m_Name")
+                    .and_then(Value::as_str)
+                    .filter(|name| !name.is_empty())
+                    .map_or_else(|| format!("#{i}"), |name| format!("'{name}'"));
+                (name, pass)
+            })
+            .collect::<BTreeMap<_, _>>()
+    });
+    let changes = passes.as_ref().changes(|passes| passes.keys());
+    for name in changes.removed {
+        writeln!(out, "{prefix} pass {name} removed")?;
+    }
+    for name in changes.added {
+        writeln!(out, "{prefix} pass {name} added")?;
+    }
+    for name in changes.same {
+        let pass = passes.as_ref().map(|passes| passes[name]);
+        diff_pass(cx, out, class, &format!("{prefix} pass {name}"), pass)?;
+    }
+    Ok(())
+}
+
+fn diff_pass(
+    cx: &Context,
+    out: &mut String,
+    class: &str,
+    prefix: &str,
+    pass: OldNew<&Value>,
+) -> Result<()> {
+    let tags = pass.map(|pass| named(pass, "/m_Tags/tags", values::render_any));
+    diff_named(out, &format!("{prefix} tag"), tags.as_ref())?;
+
+    for &(program, stage) in PROGRAMS {
+        let variants = pass.map(|pass| {
+            pass.get(program)
+                .map_or(0, |program| array(program, "m_SubPrograms").len())
+        });
+        if variants.changed() {
+            writeln!(
+                out,
+                "{prefix} {stage} variants {} -> {}",
+                variants.old, variants.new
+            )?;
+        }
+    }
+
+    // blend modes, culling, depth testing etc.
+    let state = pass.map(|pass| pass.get("m_State").cloned().unwrap_or_default());
+    let state_diff = values::diff_json_without(
+        cx,
+        state,
+        &["m_Name", "m_Tags"],
+        &format!("{class}.m_ParsedForm.m_SubShaders.m_Passes.m_State"),
+    )?;
+    if !state_diff.is_empty() {
+        writeln!(out, "{prefix} render state")?;
+        for line in state_diff.lines() {
+            writeln!(out, "  {line}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Decodes the `fsm` of PlayMakerFSM components and FsmTemplates into states, transitions,
+//! events, variables and actions, whose parameters are otherwise packed into byte arrays.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::diff::values::{self, array, string};
+use crate::old_new::OldNew;
+
+use super::matching;
+
+struct Fsm {
+    name: String,
+    start_state: String,
+    /// name -> is global
+    events: BTreeMap<String, bool>,
+    /// event -> target state
+    global_transitions: BTreeMap<String, String>,
+    /// `Float 'speed'` -> value
+    variables: BTreeMap<String, String>,
+    states: IndexMap<String, State>,
+}
+
+struct State {
+    /// event -> target state
+    transitions: BTreeMap<String, String>,
+    actions: Vec<Action>,
+}
+
+struct Action {
+    /// The type name without its namespace, like `Wait`
+    name: String,
+    enabled: bool,
+    params: Vec<(String, String)>,
+}
+
+/// Whether both versions of a MonoBehaviour contain an `fsm`, like PlayMakerFSM components and FsmTemplates.
+pub(super) fn is_fsm(value: OldNew<&Value>) -> bool {
+    let has_fsm = |value: &Value| value.pointer("/fsm/states").is_some();
+    has_fsm(value.old) && has_fsm(value.new)
+}
+
+/// Diffs a value containing an `fsm`, see [`is_fsm`], by its decoded FSM and everything else as JSON.
+pub(super) fn diff(cx: &Context, mut value: OldNew<Value>, class: &str) -> Result<String> {
+    let take_fsm = |value: &mut Value| {
+        let fsm = value.as_object_mut().unwrap().remove("fsm").unwrap();
+        Fsm::decode(&fsm)
+    };
+    let fsm = OldNew::new(take_fsm(&mut value.old), take_fsm(&mut value.new));
+    let mut out = crate::diff::diff_json(cx, value.as_ref(), class)?;
+
+    let fsm_diff = diff_fsm(fsm.as_ref())?;
+    if !fsm_diff.is_empty() {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(fsm_diff.trim_end());
+    }
+    Ok(out)
+}
+
+impl Fsm {
+    fn decode(fsm: &Value) -> Fsm {
+        let events = array(fsm, "events")
+            .iter()
+            .map(|event| {
+                let global = event.get("isGlobal").and_then(Value::as_bool);
+                (string(event, "name").to_owned(), global.unwrap_or(false))
+            })
+            .collect();
+
+        let mut variables = BTreeMap::new();
+        if let Some(groups) = fsm.get("variables").and_then(Value::as_object) {
+            for (group, group_variables) in groups {
+                // e.g. `floatVariables`, next to lists like `categories`
+                let Some(kind) = group.strip_suffix("Variables") else {
+                    continue;
+                };
+                let kind = capitalize(kind);
+                for variable in group_variables.as_array().into_iter().flatten() {
+                    let name = string(variable, "name");
+                    variables.insert(format!("{kind} '{name}'"), fsm_value(variable));
+                }
+            }
+        }
+
+        let states = array(fsm, "states")
+            .iter()
+            .map(|state| {
+                let state_data = State {
+                    transitions: transitions(array(state, "transitions")),
+                    actions: state.get("actionData").map(actions).unwrap_or_default(),
+                };
+                (string(state, "name").to_owned(), state_data)
+            })
+            .collect();
+
+        Fsm {
+            name: string(fsm, "name").to_owned(),
+            start_state: string(fsm, "startState").to_owned(),
+            events,
+            global_transitions: transitions(array(fsm, "globalTransitions")),
+            variables,
+            states,
+        }
+    }
+}
+
+fn transitions(transitions: &[Value]) -> BTreeMap<String, String> {
+    transitions
+        .iter()
+        .map(|transition| {
+            let event = transition
+                .get("fsmEvent")
+                .map(|event| string(event, "name"))
+                .unwrap_or_default();
+            (event.to_owned(), string(transition, "toState").to_owned())
+        })
+        .collect()
+}
+
+/// Decodes the parameters of every action from the parallel arrays of `ActionData`.
+fn actions(data: &Value) -> Vec<Action> {
+    let names = array(data, "actionNames");
+    let enabled = array(data, "actionEnabled");
+    let start_index = array(data, "actionStartIndex");
+    let param_names = array(data, "paramName");
+    let param_types = array(data, "paramDataType");
+    let param_positions = array(data, "paramDataPos");
+    let param_sizes = array(data, "paramByteDataSize");
+    let bytes: Vec<u8> = array(data, "byteData")
+        .iter()
+        .filter_map(|byte| Some(byte.as_u64()? as u8))
+        .collect();
+
+    let index = |values: &[Value], i: usize| values.get(i).and_then(Value::as_u64).unwrap_or(0);
+
+    (0..names.len())
+        .map(|i| {
+            let start = index(start_index, i) as usize;
+            let end = start_index
+                .get(i + 1)
+                .and_then(Value::as_u64)
+                .map_or(param_names.len(), |end| end as usize);
+
+            let params = (start..end.min(param_names.len()))
+                .map(|j| {
+                    let name = param_names[j].as_str().unwrap_or_default().to_owned();
+                    let value = param_value(
+                        data,
+                        &bytes,
+                        index(param_types, j),
+                        index(param_positions, j) as usize,
+                        index(param_sizes, j) as usize,
+                    );
+                    (name, value)
+                })
+                .collect();
+
+            let full_name = names[i].as_str().unwrap_or_default();
+            let full_name = full_name.split(',').next().unwrap_or_default();
+            Action {
+                name: full_name.rsplit('.').next().unwrap_or_default().to_owned(),
+                enabled: enabled.get(i).and_then(Value::as_bool).unwrap_or(true),
+                params,
+            }
+        })
+        .collect()
+}
+
+/// Renders a parameter of PlayMaker's `ParamDataType`. Primitives are stored in `byteData`,
+/// everything else in a list per type.
+fn param_value(data: &Value, bytes: &[u8], kind: u64, pos: usize, size: usize) -> String {
+    let bytes_at = |len: usize| bytes.get(pos..pos + len);
+    let floats = |count: usize| -> Option<String> {
+        let floats: Vec<String> = bytes_at(count * 4)?
+            .chunks_exact(4)
+            .map(|float| f32::from_le_bytes(float.try_into().unwrap()).to_string())
+            .collect();
+        Some(format!("({})", floats.join(", ")))
+    };
+    let listed = |list: &str| array(data, list).get(pos).map(fsm_value);
+
+    let value = match kind {
+        // Integer, LayerMask, Enum
+        0 | 18 | 19 => {
+            bytes_at(4).map(|int| i32::from_le_bytes(int.try_into().unwrap()).to_string())
+        }
+        1 => bytes_at(1).map(|byte| (byte[0] != 0).to_string()),
+        // String, FsmEvent
+        2 | 16 => bytes_at(size).map(|string| format!("{:?}", String::from_utf8_lossy(string))),
+        9 => bytes_at(4).map(|float| f32::from_le_bytes(float.try_into().unwrap()).to_string()),
+        4 => floats(2),
+        5 => floats(3),
+        // Color, Vector4, Rect, Quaternion
+        3 | 6 | 7 | 31 => floats(4),
+        8 => listed("animationCurveParams"),
+        10 => listed("fsmFloatParams"),
+        11 => listed("fsmIntParams"),
+        12 => listed("fsmBoolParams"),
+        13 => listed("fsmStringParams"),
+        14 => listed("fsmGameObjectParams"),
+        15 => listed("fsmOwnerDefaultParams"),
+        17 => listed("fsmVector3Params"),
+        // ObjectReference, GameObject
+        20 | 24 => listed("unityObjectParams"),
+        // FsmObject, FsmMaterial, FsmTexture
+        21 | 32 | 33 => listed("fsmObjectParams"),
+        22 => listed("fsmColorParams"),
+        25 => listed("fsmVector2Params"),
+        26 => listed("fsmRectParams"),
+        27 => listed("fsmQuaternionParams"),
+        28 => listed("fsmEventTargetParams"),
+        29 => listed("fsmPropertyParams"),
+        30 => listed("fsmTemplateControlParams"),
+        34 => listed("functionCallParams"),
+        35 => listed("fsmArrayParams"),
+        // the elements follow as their own parameters
+        36 => array(data, "arrayParamSizes")
+            .get(pos)
+            .and_then(Value::as_u64)
+            .map(|len| format!("array of {len}")),
+        37 => listed("fsmEnumParams"),
+        38 => listed("fsmVarParams"),
+        39 => listed("layerOptionParams"),
+        _ => None,
+    };
+    value.unwrap_or_else(|| format!("<ParamDataType {kind}>"))
+}
+
+/// Renders FSM variables like `FsmFloat` as their value, or the variable they refer to.
+fn fsm_value(value: &Value) -> String {
+    if value.get("useVariable").and_then(Value::as_bool) == Some(true) {
+        return format!("${}", string(value, "name"));
+    }
+    // FsmOwnerDefault
+    if let Some(option) = value.get("ownerOption").and_then(Value::as_u64) {
+        return match (option, value.get("gameObject")) {
+            (1, Some(game_object)) => fsm_value(game_object),
+            _ => "owner".to_owned(),
+        };
+    }
+    match value.get("value") {
+        Some(inner) => values::render_any(inner),
+        None => {
+            let mut value = value.clone();
+            if let Some(map) = value.as_object_mut() {
+                for key in ["name", "tooltip", "showInInspector", "networkSync"] {
+                    map.remove(key);
+                }
+            }
+            values::render_any(&value)
+        }
+    }
+}
+
+fn diff_fsm(fsm: OldNew<&Fsm>) -> Result<String> {
+    let mut out = String::new();
+
+    let name = fsm.map(|fsm| fsm.name.as_str());
+    if name.changed() {
+        writeln!(out, "fsm renamed '{}' -> '{}'", name.old, name.new)?;
+    }
+    let start_state = fsm.map(|fsm| fsm.start_state.as_str());
+    if start_state.changed() {
+        writeln!(
+            out,
+            "start state '{}' -> '{}'",
+            start_state.old, start_state.new
+        )?;
+    }
+
+    let events = fsm.map(|fsm| &fsm.events);
+    let changes = events.changes(|events| events.keys());
+    for event in changes.removed {
+        writeln!(out, "event '{event}' removed")?;
+    }
+    for event in changes.added {
+        writeln!(out, "event '{event}' added")?;
+    }
+    for event in changes.same {
+        match (events.old[event], events.new[event]) {
+            (false, true) => writeln!(out, "event '{event}' is now global")?,
+            (true, false) => writeln!(out, "event '{event}' is no longer global")?,
+            _ => {}
+        }
+    }
+
+    diff_map(
+        &mut out,
+        fsm.map(|fsm| &fsm.variables),
+        |out, variable, value| writeln!(out, "variable {variable} removed ({value})"),
+        |out, variable, value| writeln!(out, "variable {variable} added ({value})"),
+        |out, variable, value| writeln!(out, "variable {variable} {} -> {}", value.old, value.new),
+    )?;
+    diff_transitions(
+        &mut out,
+        "global transition",
+        fsm.map(|fsm| &fsm.global_transitions),
+    )?;
+
+    let states = fsm.map(|fsm| &fsm.states);
+    let changes = states.changes(|states| states.keys());
+    for state in changes.removed {
+        writeln!(out, "state '{state}' removed")?;
+    }
+    for state in changes.added {
+        let actions: Vec<_> = states.new[state]
+            .actions
+            .iter()
+            .map(|action| action.name.as_str())
+            .collect();
+        writeln!(out, "state '{state}' added [{}]", actions.join(", "))?;
+    }
+    // in the order of the new version, like the PlayMaker editor lists them
+    for (state, new) in states.new {
+        let Some(old) = states.old.get(state) else {
+            continue;
+        };
+        diff_transitions(
+            &mut out,
+            &format!("state '{state}' transition"),
+            OldNew::new(&old.transitions, &new.transitions),
+        )?;
+        diff_actions(
+            &mut out,
+            state,
+            OldNew::new(&old.actions[..], &new.actions[..]),
+        )?;
+    }
+
+    Ok(out)
+}
+
+fn diff_transitions(
+    out: &mut String,
+    prefix: &str,
+    transitions: OldNew<&BTreeMap<String, String>>,
+) -> Result<()> {
+    diff_map(
+        out,
+        transitions,
+        |out, event, target| writeln!(out, "{prefix} '{event}' to '{target}' removed"),
+        |out, event, target| writeln!(out, "{prefix} '{event}' to '{target}' added"),
+        |out, event, target| writeln!(out, "{prefix} '{event}' now targets '{}'", target.new),
+    )
+}
+
+fn diff_map(
+    out: &mut String,
+    map: OldNew<&BTreeMap<String, String>>,
+    removed: impl Fn(&mut String, &str, &str) -> std::fmt::Result,
+    added: impl Fn(&mut String, &str, &str) -> std::fmt::Result,
+    changed: impl Fn(&mut String, &str, OldNew<&str>) -> std::fmt::Result,
+) -> Result<()> {
+    let changes = map.changes(|map| map.keys());
+    for key in changes.removed {
+        removed(out, key, &map.old[key])?;
+    }
+    for key in changes.added {
+        added(out, key, &map.new[key])?;
+    }
+    for key in changes.same {
+        let value = map.map(|map| map[key].as_str());
+        if value.changed() {
+            changed(out, key, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Matches the actions of a state by their types, so that inserting an action
+/// doesn't report every following one as changed.
+fn diff_actions(out: &mut String, state: &str, actions: OldNew<&[Action]>) -> Result<()> {
+    let pairs = matching::align(actions.map(|actions| {
+        actions
+            .iter()
+            .map(|action| action.name.as_str())
+            .collect::<Vec<_>>()
+    }));
+
+    for pair in pairs {
+        match pair {
+            (Some(i), None) => {
+                let action = &actions.old[i];
+                writeln!(out, "state '{state}' action {i} `{}` removed", action.name)?;
+            }
+            (None, Some(i)) => {
+                let action = &actions.new[i];
+                let params: Vec<_> = action
+                    .params
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect();
+                writeln!(
+                    out,
+                    "state '{state}' action {i} `{}` added ({})",
+                    action.name,
+                    params.join(", ")
+                )?;
+            }
+            (Some(old), Some(i)) => {
+                let action = OldNew::new(&actions.old[old], &actions.new[i]);
+                let name = &action.new.name;
+                match (action.old.enabled, action.new.enabled) {
+                    (true, false) => writeln!(out, "state '{state}' action {i} `{name}` disabled")?,
+                    (false, true) => writeln!(out, "state '{state}' action {i} `{name}` enabled")?,
+                    _ => {}
+                }
+
+                let params = action.map(|action| &action.params);
+                for (j, new) in params.new.iter().enumerate() {
+                    let (param, new) = (&new.0, &new.1);
+                    match params.old.get(j) {
+                        Some((_, old)) if old == new => {}
+                        Some((_, old)) => writeln!(
+                            out,
+                            "state '{state}' action {i} `{name}.{param}` {old} -> {new}"
+                        )?,
+                        None => writeln!(
+                            out,
+                            "state '{state}' action {i} `{name}.{param}` added ({new})"
+                        )?,
+                    }
+                }
+                for (param, _) in params.old.iter().skip(params.new.len()) {
+                    writeln!(out, "state '{state}' action {i} `{name}.{param}` removed")?;
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use rabex::typetree::TypeTreeNode;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::old_new::OldNew;
+
+/// Serialized fields added to or removed from `MonoBehaviour` scripts, collected over the whole run
+/// so that a schema change is reported once per script instead of once per instance.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ScriptFieldChanges {
+    scripts: BTreeMap<String, ScriptFields>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ScriptFields {
+    added: BTreeMap<String, FieldStats>,
+    removed: BTreeMap<String, FieldStats>,
+    retyped: BTreeMap<String, OldNew<String>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct FieldStats {
+    ty: String,
+    instances: usize,
+    values: BTreeMap<String, usize>,
+}
+impl FieldStats {
+    fn record(&mut self, ty: &str, value: Option<&serde_json::Value>) {
+        if self.ty.is_empty() {
+            self.ty = ty.to_owned();
+        }
+        self.instances += 1;
+        let value = value.map_or_else(|| "?".to_owned(), |value| value.to_string());
+        *self.values.entry(value).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &FieldStats) {
+        if self.ty.is_empty() {
+            self.ty = other.ty.clone();
+        }
+        self.instances += other.instances;
+        for (value, count) in &other.values {
+            *self.values.entry(value.clone()).or_default() += count;
+        }
+    }
+}
+
+fn fields(tt: &TypeTreeNode) -> BTreeMap<&str, &str> {
+    tt.children
+        .iter()
+        .map(|field| (field.m_Name.as_str(), field.m_Type.as_str()))
+        .collect()
+}
+
+impl ScriptFieldChanges {
+    /// Compares the top-level fields of the script's typetrees.
+    /// Added and removed fields are recorded and stripped from `value`, so that they don't show up in the per-object diff.
+    pub fn record(
+        &mut self,
+        script: &str,
+        tt: OldNew<&TypeTreeNode>,
+        value: &mut OldNew<serde_json::Value>,
+    ) {
+        let fields = tt.map(fields);
+        if !fields.changed() {
+            return;
+        }
+
+        let entry = self.scripts.entry(script.to_owned()).or_default();
+        let changes = fields.changes(|fields| fields.keys().copied());
+
+        for name in changes.added {
+            let removed = value.new.as_object_mut().and_then(|map| map.remove(name));
+            entry
+                .added
+                .entry(name.to_owned())
+                .or_default()
+                .record(fields.new[name], removed.as_ref());
+        }
+        for name in changes.removed {
+            let removed = value.old.as_object_mut().and_then(|map| map.remove(name));
+            entry
+                .removed
+                .entry(name.to_owned())
+                .or_default()
+                .record(fields.old[name], removed.as_ref());
+        }
+        for name in changes.same {
+            let ty = fields.as_ref().map(|fields| fields[name]);
+            if ty.changed() {
+                entry
+                    .retyped
+                    .entry(name.to_owned())
+                    .or_insert_with(|| ty.map(str::to_owned));
+            }
+        }
+    }
+
+    /// Adds the changes collected for another file, e.g. one reused from a previous run.
+    pub fn merge(&mut self, other: &ScriptFieldChanges) {
+        for (script, other) in &other.scripts {
+            let entry = self.scripts.entry(script.clone()).or_default();
+            for (fields, other) in [
+                (&mut entry.added, &other.added),
+                (&mut entry.removed, &other.removed),
+            ] {
+                for (name, stats) in other {
+                    fields.entry(name.clone()).or_default().merge(stats);
+                }
+            }
+            for (name, ty) in &other.retyped {
+                entry
+                    .retyped
+                    .entry(name.clone())
+                    .or_insert_with(|| ty.clone());
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> std::fmt::Result {
+        for (script, fields) in &self.scripts {
+            for (name, stats) in &fields.added {
+                write!(w, "field `{name}` ({}) added to `{script}`", stats.ty)?;
+                write_values(w, stats)?;
+            }
+            for (name, stats) in &fields.removed {
+                write!(w, "field `{name}` ({}) removed from `{script}`", stats.ty)?;
+                write_values(w, stats)?;
+            }
+            for (name, ty) in &fields.retyped {
+                writeln!(
+                    w,
+                    "field `{name}` of `{script}` retyped {} -> {}",
+                    ty.old, ty.new
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_values(w: &mut impl Write, stats: &FieldStats) -> std::fmt::Result {
+    const MAX_VALUES: usize = 5;
+
+    let mut values: Vec<_> = stats.values.iter().collect();
+    values.sort_by(|a, b| b.1.cmp(a.1));
+
+    for (i, (value, count)) in values.iter().take(MAX_VALUES).enumerate() {
+        let sep = if i == 0 { ", " } else { "; " };
+        let plural = if **count == 1 { "" } else { "s" };
+        write!(w, "{sep}{value} in {count} instance{plural}")?;
+    }
+    if values.len() > MAX_VALUES {
+        let rest: usize = values[MAX_VALUES..].iter().map(|(_, count)| **count).sum();
+        write!(w, "; {rest} more with other values")?;
+    }
+    writeln!(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn script(fields: &[(&str, &str)]) -> TypeTreeNode {
+        TypeTreeNode {
+            m_Type: "MonoBehaviour".to_owned(),
+            m_Name: "Base".to_owned(),
+            children: fields
+                .iter()
+                .map(|&(name, ty)| TypeTreeNode {
+                    m_Type: ty.to_owned(),
+                    m_Name: name.to_owned(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Records one instance of `HeroController` whose `silk` field was added
+    fn record_instance(changes: &mut ScriptFieldChanges, silk: serde_json::Value) {
+        let tt = OldNew::new(
+            script(&[("health", "int"), ("speed", "float"), ("legacy", "string")]),
+            script(&[("health", "int"), ("speed", "double"), ("silk", "int")]),
+        );
+        let mut value = OldNew::new(
+            json!({ "health": 5, "speed": 1.0, "legacy": "a" }),
+            json!({ "health": 5, "speed": 1.0, "silk": silk }),
+        );
+        changes.record("HeroController", tt.as_ref(), &mut value);
+
+        // the added and removed fields are reported once per script instead of per object
+        assert_eq!(value.old, json!({ "health": 5, "speed": 1.0 }));
+        assert_eq!(value.new, json!({ "health": 5, "speed": 1.0 }));
+    }
+
+    fn written(changes: &ScriptFieldChanges) -> String {
+        let mut out = String::new();
+        changes.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn unchanged() {
+        let mut changes = ScriptFieldChanges::default();
+        let tt = script(&[("health", "int")]);
+        let mut value = OldNew::new(json!({ "health": 5 }), json!({ "health": 6 }));
+        changes.record("HeroController", OldNew::new(&tt, &tt), &mut value);
+
+        assert!(changes.is_empty());
+        assert_eq!(value.new, json!({ "health": 6 }));
+    }
+
+    #[test]
+    fn record() {
+        let mut changes = ScriptFieldChanges::default();
+        record_instance(&mut changes, json!(3));
+        record_instance(&mut changes, json!(3));
+        record_instance(&mut changes, json!(0));
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            written(&changes),
+            "field `silk` (int) added to `HeroController`, 3 in 2 instances; 0 in 1 instance\n\
+             field `legacy` (string) removed from `HeroController`, \"a\" in 3 instances\n\
+             field `speed` of `HeroController` retyped float -> double\n"
+        );
+    }
+
+    #[test]
+    fn many_values() {
+        let mut changes = ScriptFieldChanges::default();
+        for silk in 0..7 {
+            record_instance(&mut changes, json!(silk));
+        }
+        record_instance(&mut changes, json!(6));
+
+        let out = written(&changes);
+        let added = out.lines().next().unwrap();
+        assert_eq!(
+            added,
+            "field `silk` (int) added to `HeroController`, 6 in 2 instances; 0 in 1 instance; \
+             1 in 1 instance; 2 in 1 instance; 3 in 1 instance; 2 more with other values"
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let mut first = ScriptFieldChanges::default();
+        record_instance(&mut first, json!(3));
+
+        // the second file is replayed from the run log
+        let mut second = ScriptFieldChanges::default();
+        record_instance(&mut second, json!(3));
+        record_instance(&mut second, json!(1));
+        let second: ScriptFieldChanges =
+            serde_json::from_str(&serde_json::to_string(&second).unwrap()).unwrap();
+
+        first.merge(&second);
+        assert_eq!(
+            written(&first),
+            "field `silk` (int) added to `HeroController`, 3 in 2 instances; 1 in 1 instance\n\
+             field `legacy` (string) removed from `HeroController`, \"a\" in 3 instances\n\
+             field `speed` of `HeroController` retyped float -> double\n"
+        );
+
+        let mut empty = ScriptFieldChanges::default();
+        empty.merge(&first);
+        assert_eq!(written(&empty), written(&first));
+    }
+}
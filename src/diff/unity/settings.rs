@@ -0,0 +1,352 @@
+//! Diffs the project settings stored in `globalgamemanagers` by their structure,
+//! like the scene list, layers and the layer collision matrix.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use anyhow::Result;
+use rabex::objects::ClassId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::diff::values::{self, array, string};
+use crate::old_new::OldNew;
+
+use super::matching;
+
+const LAYERS: usize = 32;
+
+pub(super) fn is_settings(class_id: ClassId) -> bool {
+    matches!(
+        class_id,
+        ClassId::BuildSettings
+            | ClassId::TagManager
+            | ClassId::Physics2DSettings
+            | ClassId::PhysicsManager
+            | ClassId::PlayerSettings
+            | ClassId::QualitySettings
+            | ClassId::InputManager
+    )
+}
+
+pub(super) fn diff_settings<P: TypeTreeProvider>(
+    cx: &Context,
+    file: OldNew<&SerializedFileHandle<'_, GameFiles, P>>,
+    class_id: ClassId,
+    value: OldNew<Value>,
+    class: &str,
+) -> Result<String> {
+    let mut out = String::new();
+    let fields: &[&str] = match class_id {
+        ClassId::BuildSettings => {
+            let scenes = value.as_ref().map(|value| strings(value, "scenes"));
+            diff_order(&mut out, "scene", scenes)?;
+            &["scenes"]
+        }
+        ClassId::TagManager => {
+            let tags = value.as_ref().map(|value| strings(value, "tags"));
+            diff_order(&mut out, "tag", tags)?;
+
+            let layers = value.as_ref().map(|value| strings(value, "layers"));
+            for i in 0..LAYERS {
+                let layer = layers.as_ref().map(|layers| layer_name(layers, i));
+                if layer.changed() {
+                    writeln!(out, "layer {i} '{}' -> '{}'", layer.old, layer.new)?;
+                }
+            }
+
+            let sorting_layers = value.as_ref().map(|value| {
+                array(value, "m_SortingLayers")
+                    .iter()
+                    .filter_map(|layer| layer.get("name")?.as_str())
+                    .collect::<Vec<_>>()
+            });
+            diff_order(&mut out, "sorting layer", sorting_layers)?;
+            &["tags", "layers", "m_SortingLayers"]
+        }
+        ClassId::Physics2DSettings | ClassId::PhysicsManager => {
+            let layers = tag_manager_layers(file.new).unwrap_or_default();
+            let matrix = value.as_ref().map(collision_matrix);
+            write_collision_matrix(&mut out, &layers, matrix)?;
+            &["m_LayerCollisionMatrix"]
+        }
+        ClassId::PlayerSettings => {
+            for (field, label) in [
+                ("companyName", "company"),
+                ("productName", "product"),
+                ("bundleVersion", "version"),
+            ] {
+                let text = value.as_ref().map(|value| string(value, field));
+                if text.changed() {
+                    writeln!(out, "{label} '{}' -> '{}'", text.old, text.new)?;
+                }
+            }
+
+            // `;` separated per platform
+            let defines = value.as_ref().map(|value| {
+                values::pairs(value, "scriptingDefineSymbols")
+                    .map(|(platform, symbols)| {
+                        let symbols: BTreeSet<&str> = symbols
+                            .as_str()
+                            .unwrap_or_default()
+                            .split(';')
+                            .filter(|symbol| !symbol.is_empty())
+                            .collect();
+                        (key(platform), symbols)
+                    })
+                    .collect::<BTreeMap<_, _>>()
+            });
+            let platforms = defines.as_ref().changes(|defines| defines.keys());
+            for platform in platforms
+                .removed
+                .iter()
+                .chain(&platforms.added)
+                .chain(&platforms.same)
+            {
+                let symbols = defines.as_ref().map(|defines| defines.get(*platform));
+                let symbols = symbols.map(|symbols| symbols.cloned().unwrap_or_default());
+                let changes = symbols.as_ref().changes(|symbols| symbols.iter().copied());
+                for symbol in changes.removed {
+                    writeln!(out, "define '{symbol}' removed for {platform}")?;
+                }
+                for symbol in changes.added {
+                    writeln!(out, "define '{symbol}' added for {platform}")?;
+                }
+            }
+            &[
+                "companyName",
+                "productName",
+                "bundleVersion",
+                "scriptingDefineSymbols",
+            ]
+        }
+        ClassId::QualitySettings => {
+            let levels = value.as_ref().map(|value| {
+                array(value, "m_QualitySettings")
+                    .iter()
+                    .map(|level| (string(level, "name"), level))
+                    .collect::<Vec<_>>()
+            });
+            let names = levels
+                .as_ref()
+                .map(|levels| levels.iter().map(|(name, _)| *name).collect());
+            diff_order(&mut out, "quality level", names)?;
+
+            let current = value.as_ref().map_zip(&levels, |value, levels| {
+                let index = value.get("m_CurrentQuality").and_then(Value::as_u64);
+                index
+                    .and_then(|index| levels.get(index as usize))
+                    .map_or("none", |(name, _)| *name)
+            });
+            if current.changed() {
+                writeln!(
+                    out,
+                    "current quality '{}' -> '{}'",
+                    current.old, current.new
+                )?;
+            }
+
+            // levels with the same name are matched by their order
+            let named = levels.map(by_name);
+            for name in named.as_ref().changes(|levels| levels.keys()).same {
+                let level = named.as_ref().map(|levels| levels[name]);
+                let diff =
+                    crate::diff::diff_json(cx, level, &format!("{class}.m_QualitySettings"))?;
+                if !diff.is_empty() {
+                    writeln!(out, "quality level {name}")?;
+                    for line in diff.lines() {
+                        writeln!(out, "  {line}")?;
+                    }
+                }
+            }
+            &["m_QualitySettings", "m_CurrentQuality"]
+        }
+        ClassId::InputManager => {
+            diff_axes(&mut out, value.as_ref())?;
+            &["m_Axes"]
+        }
+        _ => &[],
+    };
+
+    out.push_str(&values::diff_json_without(cx, value, fields, class)?);
+
+    Ok(out)
+}
+
+fn strings<'a>(value: &'a Value, key: &str) -> Vec<&'a str> {
+    array(value, key)
+        .iter()
+        .map(|value| value.as_str().unwrap_or_default())
+        .collect()
+}
+
+/// A map key, which is a platform name or an enum value
+fn key(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| values::render_any(value), str::to_owned)
+}
+
+fn layer_name<S: AsRef<str>>(layers: &[S], i: usize) -> &str {
+    layers.get(i).map_or("", AsRef::as_ref)
+}
+
+/// Reports added and removed entries of an ordered list, and entries whose position relative to the others changed.
+fn diff_order(out: &mut String, kind: &str, items: OldNew<Vec<&str>>) -> Result<()> {
+    let aligned = matching::align(items.clone());
+    let unpaired = |side: fn(&(Option<usize>, Option<usize>)) -> Option<usize>| {
+        aligned
+            .iter()
+            .filter(|pair| pair.0.is_none() || pair.1.is_none())
+            .filter_map(side)
+            .collect::<Vec<_>>()
+    };
+    let removed = unpaired(|pair| pair.0);
+    let added = unpaired(|pair| pair.1);
+
+    for &i in &removed {
+        let name = items.old[i];
+        match added.iter().find(|&&j| items.new[j] == name) {
+            Some(j) => writeln!(out, "{kind} '{name}' moved from {i} to {j}")?,
+            None => writeln!(out, "{kind} '{name}' removed (was {i})")?,
+        }
+    }
+    for &j in &added {
+        let name = items.new[j];
+        if !removed.iter().any(|&i| items.old[i] == name) {
+            writeln!(out, "{kind} '{name}' added at {j}")?;
+        }
+    }
+    Ok(())
+}
+
+/// The layer names of the `TagManager` in the same file
+fn tag_manager_layers<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+) -> Result<Vec<String>> {
+    for info in file.file.objects() {
+        let object = file.object_at::<Value>(info.m_PathID)?;
+        if object.class_id() == ClassId::TagManager {
+            let value = object.read()?;
+            let layers = strings(&value, "layers");
+            return Ok(layers.into_iter().map(str::to_owned).collect());
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Bit `j` of row `i` is set if layers `i` and `j` collide
+fn collision_matrix(value: &Value) -> Vec<u32> {
+    array(value, "m_LayerCollisionMatrix")
+        .iter()
+        .map(|row| row.as_u64().unwrap_or_default() as u32)
+        .collect()
+}
+
+/// Writes the lower triangle of the matrix for the named layers and the layers whose collisions changed,
+/// marking pairs that now collide with `+` and pairs that no longer collide with `-`.
+fn write_collision_matrix(
+    out: &mut String,
+    names: &[String],
+    matrix: OldNew<Vec<u32>>,
+) -> Result<()> {
+    let collides =
+        |matrix: &[u32], i: usize, j: usize| matrix.get(i).is_some_and(|row| row & (1 << j) != 0);
+    let changed = |i: usize, j: usize| collides(&matrix.old, i, j) != collides(&matrix.new, i, j);
+    if !(0..LAYERS).any(|i| (0..=i).any(|j| changed(i, j))) {
+        return Ok(());
+    }
+
+    let shown: Vec<usize> = (0..LAYERS)
+        .filter(|&i| !layer_name(names, i).is_empty() || (0..LAYERS).any(|j| changed(i, j)))
+        .collect();
+    let width = shown
+        .iter()
+        .map(|&i| layer_name(names, i).len())
+        .max()
+        .unwrap_or(0);
+
+    writeln!(
+        out,
+        "layer collision matrix (+ now collides, - no longer collides)"
+    )?;
+    write!(out, "{:width$}   ", "")?;
+    for &j in &shown {
+        write!(out, "{j:>3}")?;
+    }
+    writeln!(out)?;
+    for &i in &shown {
+        write!(out, "{:>width$} {i:>2}", layer_name(names, i))?;
+        for &j in shown.iter().take_while(|&&j| j <= i) {
+            let cell = match (collides(&matrix.old, i, j), collides(&matrix.new, i, j)) {
+                (false, true) => '+',
+                (true, false) => '-',
+                (true, true) => 'x',
+                (false, false) => '.',
+            };
+            write!(out, "{cell:>3}")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Keys entries by their quoted name. Later entries with the same name get `#2`, `#3`, etc. appended.
+fn by_name<'a, T>(entries: impl IntoIterator<Item = (&'a str, T)>) -> BTreeMap<String, T> {
+    let mut seen = BTreeMap::<&str, usize>::new();
+    entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let index = seen.entry(name).or_default();
+            *index += 1;
+            let key = match *index {
+                1 => format!("'{name}'"),
+                index => format!("'{name}' #{index}"),
+            };
+            (key, entry)
+        })
+        .collect()
+}
+
+/// Axes are matched by name, counting repeated names like the keyboard and joystick `Horizontal` axis.
+fn diff_axes(out: &mut String, value: OldNew<&Value>) -> Result<()> {
+    let axes = value.map(|value| {
+        by_name(
+            array(value, "m_Axes")
+                .iter()
+                .map(|axis| (string(axis, "m_Name"), axis)),
+        )
+    });
+    let axes = axes.as_ref();
+
+    let changes = axes.changes(|axes| axes.keys());
+    for key in changes.removed {
+        writeln!(out, "axis {key} removed")?;
+    }
+    for key in changes.added {
+        writeln!(out, "axis {key} added")?;
+    }
+    for key in changes.same {
+        let axis = axes.map(|axes| axes[key].as_object());
+        let (Some(old), Some(new)) = (axis.old, axis.new) else {
+            continue;
+        };
+        let new_fields = new.keys().filter(|field| !old.contains_key(*field));
+        for field in old.keys().chain(new_fields) {
+            let old_value = old.get(field).unwrap_or(&Value::Null);
+            let new_value = new.get(field).unwrap_or(&Value::Null);
+            if old_value != new_value {
+                writeln!(
+                    out,
+                    "axis {key} {field} {} -> {}",
+                    values::render_any(old_value),
+                    values::render_any(new_value)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
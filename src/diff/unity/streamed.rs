@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use rabex::files::bundlefile::BundleFileReader;
+use rustc_hash::FxHashMap;
+
+/// Where the data streamed out of a serialized file (`.resS`, `.resource`) is stored.
+pub(super) enum StreamedData<'a> {
+    /// Next to the serialized file on disk
+    Directory(PathBuf),
+    /// In the bundle containing the serialized file
+    Bundle {
+        bundle: &'a BundleFileReader<Cursor<&'a [u8]>>,
+        /// Bundle entries are decompressed as a whole, so keep them around for the next object
+        entries: RefCell<FxHashMap<String, Vec<u8>>>,
+    },
+}
+
+impl<'a> StreamedData<'a> {
+    pub fn bundle(bundle: &'a BundleFileReader<Cursor<&'a [u8]>>) -> Self {
+        StreamedData::Bundle {
+            bundle,
+            entries: RefCell::default(),
+        }
+    }
+
+    /// Reads `size` bytes at `offset` of `path`, which is either a file name
+    /// or an `archive:/CAB-.../CAB-....resS` path.
+    pub fn read(&self, path: &str, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        match self {
+            StreamedData::Directory(dir) => {
+                let path = dir.join(name);
+                let mut file =
+                    File::open(&path).with_context(|| format!("{} not found", path.display()))?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut data = vec![0; size];
+                file.read_exact(&mut data)
+                    .with_context(|| format!("{} is too short", path.display()))?;
+                Ok(data)
+            }
+            StreamedData::Bundle { bundle, entries } => {
+                let mut entries = entries.borrow_mut();
+                if !entries.contains_key(name) {
+                    let entry = bundle
+                        .read_at(name)?
+                        .with_context(|| format!("{name} not found in bundle"))?;
+                    entries.insert(name.to_owned(), entry);
+                }
+
+                let start = usize::try_from(offset)?;
+                entries[name]
+                    .get(start..start + size)
+                    .map(<[u8]>::to_vec)
+                    .with_context(|| format!("{name} is too short"))
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use serde::de::{Deserializer, Error, Visitor};
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::old_new::OldNew;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct TextAsset {
+    #[serde(deserialize_with = "string_or_bytes")]
+    m_Script: Vec<u8>,
+}
+
+/// TextAssets may contain arbitrary bytes, which are deserialized as a byte buffer instead of a string.
+fn string_or_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+    impl Visitor<'_> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a string or bytes")
+        }
+
+        fn visit_str<E: Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            Ok(v.as_bytes().to_vec())
+        }
+        fn visit_string<E: Error>(self, v: String) -> Result<Vec<u8>, E> {
+            Ok(v.into_bytes())
+        }
+        fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+        fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+
+    deserializer.deserialize_string(BytesVisitor)
+}
+
+pub(super) fn read<P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    path_id: PathId,
+) -> Result<Vec<u8>> {
+    Ok(file.object_at::<TextAsset>(path_id)?.read()?.m_Script)
+}
+
+/// Diffs the contents of a TextAsset as JSON if both versions are JSON, as text if they are UTF-8,
+/// and as a hexdump of the first difference otherwise.
+pub(super) fn diff_text_asset<P: TypeTreeProvider>(
+    cx: &Context,
+    file: OldNew<&SerializedFileHandle<'_, GameFiles, P>>,
+    path_id: OldNew<PathId>,
+) -> Result<String> {
+    let script = file.try_map_zip(&path_id, |file, &path_id| read(file, path_id))?;
+    if !script.changed() {
+        return Ok(String::new());
+    }
+
+    let mut diff = match script.as_deref().try_map(str::from_utf8) {
+        Ok(text) => match text.try_map(|text| parse_json(text).ok_or(())) {
+            Ok(json) => crate::diff::diff_json(cx, json.as_ref(), "TextAsset.m_Script")?,
+            Err(()) => crate::diff::diff_text(cx, text),
+        },
+        Err(_) => hexdump_summary(script.as_deref())?,
+    };
+    if !diff.is_empty() {
+        diff.push('\n');
+    }
+    Ok(diff)
+}
+
+/// Summarizes an added or removed TextAsset, including its text within the dump size limit.
+pub(super) fn describe<P: TypeTreeProvider>(
+    cx: &Context,
+    file: &SerializedFileHandle<'_, GameFiles, P>,
+    path_id: PathId,
+) -> Result<String> {
+    let script = read(file, path_id)?;
+    let Ok(text) = str::from_utf8(&script) else {
+        return Ok(format!("{} bytes of binary data\n", script.len()));
+    };
+
+    let mut out = format!("{} bytes, {} lines\n", text.len(), text.lines().count());
+    if let Some(limits) = cx.unity_dump_objects
+        && text.len() <= limits.max_size
+    {
+        out.push_str(text);
+        if !text.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Only objects and arrays count, plain text like `true` or `1` is better diffed as text.
+fn parse_json(text: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(text)
+        .ok()
+        .filter(|value| value.is_object() || value.is_array())
+}
+
+fn hexdump_summary(data: OldNew<&[u8]>) -> Result<String> {
+    const ROW: usize = 16;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "binary data, {} -> {} bytes",
+        data.old.len(),
+        data.new.len()
+    )?;
+
+    let first_difference = data
+        .old
+        .iter()
+        .zip(data.new)
+        .position(|(old, new)| old != new)
+        .unwrap_or(data.old.len().min(data.new.len()));
+    writeln!(out, "first difference at 0x{first_difference:x}")?;
+
+    let row_start = first_difference / ROW * ROW;
+    for (version, data) in [("old", data.old), ("new", data.new)] {
+        let row = data.get(row_start..).unwrap_or_default();
+        let row = &row[..row.len().min(ROW)];
+
+        write!(out, "{version} {row_start:08x} ")?;
+        for i in 0..ROW {
+            match row.get(i) {
+                Some(byte) => write!(out, " {byte:02x}")?,
+                None => out.push_str("   "),
+            }
+        }
+        let ascii: String = row
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(out, "  |{ascii}|")?;
+    }
+
+    Ok(out.trim_end().to_owned())
+}
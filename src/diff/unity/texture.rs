@@ -0,0 +1,598 @@
+use std::cell::{OnceCell, RefCell};
+use std::fmt::{Display, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use rabex::objects::pptr::PathId;
+use rabex::objects::{ClassId, PPtr};
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::game_files::GameFiles;
+use rabex_env::handle::SerializedFileHandle;
+use rustc_hash::FxHashMap;
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::old_new::OldNew;
+
+use super::qualify_pptrs;
+use super::streamed::StreamedData;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct Texture2D {
+    m_Width: i32,
+    m_Height: i32,
+    m_TextureFormat: i32,
+    m_MipCount: Option<i32>,
+    #[serde(rename = "image data", deserialize_with = "typeless_data")]
+    image_data: Vec<u8>,
+    m_StreamData: Option<StreamingInfo>,
+}
+
+#[derive(Deserialize)]
+struct StreamingInfo {
+    /// `UInt32` before 2020.1, `UInt64` after
+    offset: serde_json::Number,
+    size: u32,
+    path: String,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct Sprite {
+    m_RD: SpriteRenderData,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct SpriteRenderData {
+    texture: PPtr,
+    textureRect: Rect,
+}
+
+#[derive(Deserialize, PartialEq)]
+struct Rect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+impl Display for Rect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({}, {}, {}×{})",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+fn typeless_data<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("typeless data")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+
+    // `TypelessData` isn't a regular array, so it can't go through `deserialize_seq`
+    deserializer.deserialize_any(BytesVisitor)
+}
+
+/// A texture with its pixel data resolved, decoded on first use.
+struct Texture {
+    width: usize,
+    height: usize,
+    format: i32,
+    mips: Option<i32>,
+    data: Vec<u8>,
+    image: OnceCell<Result<Image, String>>,
+}
+impl Texture {
+    fn image(&self) -> Result<&Image> {
+        self.image
+            .get_or_init(|| {
+                decode(self.format, &self.data, self.width, self.height)
+                    .map_err(|e| format!("{e:#}"))
+            })
+            .as_ref()
+            .map_err(|e| anyhow!("{e}"))
+    }
+}
+
+/// Loads the textures of one version of a serialized file.
+/// Each texture is only loaded and decoded once, since an atlas is shared by many sprites.
+pub(super) struct Textures<'a, P> {
+    file: &'a SerializedFileHandle<'a, GameFiles, P>,
+    streamed: &'a StreamedData<'a>,
+    loaded: RefCell<FxHashMap<PathId, Rc<Texture>>>,
+}
+
+impl<'a, P: TypeTreeProvider> Textures<'a, P> {
+    pub fn new(
+        file: &'a SerializedFileHandle<'a, GameFiles, P>,
+        streamed: &'a StreamedData<'a>,
+    ) -> Self {
+        Textures {
+            file,
+            streamed,
+            loaded: RefCell::default(),
+        }
+    }
+
+    fn get(&self, path_id: PathId) -> Result<Rc<Texture>> {
+        if let Some(texture) = self.loaded.borrow().get(&path_id) {
+            return Ok(Rc::clone(texture));
+        }
+
+        let texture = self.file.object_at::<Texture2D>(path_id)?.read()?;
+        let data = match self.read_streamed(&texture)? {
+            Some(data) => data,
+            None => texture.image_data,
+        };
+
+        let texture = Rc::new(Texture {
+            width: texture.m_Width.try_into()?,
+            height: texture.m_Height.try_into()?,
+            format: texture.m_TextureFormat,
+            mips: texture.m_MipCount,
+            data,
+            image: OnceCell::new(),
+        });
+        self.loaded
+            .borrow_mut()
+            .insert(path_id, Rc::clone(&texture));
+        Ok(texture)
+    }
+
+    /// The image data of a texture stored in a `.resS` file, which can change without the texture object changing.
+    pub fn streamed_data(&self, path_id: PathId) -> Result<Option<Vec<u8>>> {
+        let texture = self.file.object_at::<Texture2D>(path_id)?.read()?;
+        self.read_streamed(&texture)
+    }
+
+    fn read_streamed(&self, texture: &Texture2D) -> Result<Option<Vec<u8>>> {
+        match &texture.m_StreamData {
+            Some(stream) if !stream.path.is_empty() => {
+                let offset = stream.offset.as_u64().context("invalid stream offset")?;
+                let data = self
+                    .streamed
+                    .read(&stream.path, offset, stream.size as usize)
+                    .context("reading streamed texture data")?;
+                Ok(Some(data))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The texture a sprite is cut from, and where.
+    /// `None` for sprites packed into a sprite atlas or referencing a texture in another file.
+    fn sprite(&self, path_id: PathId) -> Result<Option<(Rc<Texture>, Rect)>> {
+        let sprite = self.file.object_at::<Sprite>(path_id)?.read()?;
+        let texture = sprite.m_RD.texture;
+        if texture.is_null() || !texture.is_local() {
+            return Ok(None);
+        }
+        Ok(Some((self.get(texture.m_PathID)?, sprite.m_RD.textureRect)))
+    }
+}
+
+pub(super) fn diff_texture<P: TypeTreeProvider>(
+    textures: OldNew<&Textures<'_, P>>,
+    path_id: OldNew<PathId>,
+    name: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let texture = textures.try_map_zip(&path_id, |textures, &path_id| textures.get(path_id))?;
+    let texture = texture.as_deref();
+    let mut out = String::new();
+
+    let size = texture.map(|texture| (texture.width, texture.height));
+    if size.changed() {
+        writeln!(
+            out,
+            "size {}×{} -> {}×{}",
+            size.old.0, size.old.1, size.new.0, size.new.1
+        )?;
+    }
+    let format = texture.map(|texture| texture.format);
+    if format.changed() {
+        writeln!(
+            out,
+            "format {} -> {}",
+            format_name(format.old),
+            format_name(format.new)
+        )?;
+    }
+    if let (Some(old), Some(new)) = (texture.old.mips, texture.new.mips)
+        && old != new
+    {
+        writeln!(out, "mips {old} -> {new}")?;
+    }
+
+    if texture.map(|texture| &texture.data).changed() {
+        match texture.try_map(|texture| texture.image()) {
+            Ok(image) => compare_images(image, name, &mut out, files)?,
+            Err(e) => writeln!(out, "image data changed, could not decode: {e}")?,
+        }
+    }
+
+    Ok(out)
+}
+
+pub(super) fn diff_sprite<P: TypeTreeProvider>(
+    cx: &Context,
+    textures: OldNew<&Textures<'_, P>>,
+    path_id: OldNew<PathId>,
+    name: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let summary = textures.try_map_zip(&path_id, |textures, &path_id| -> Result<_> {
+        let value = textures.file.object_at::<Value>(path_id)?.read()?;
+        let mut summary = sprite_summary(&value);
+        qualify_pptrs(textures.file, &mut summary).context("qualifying pptrs")?;
+        Ok(summary)
+    })?;
+    let mut out = crate::diff::diff_json(cx, summary.as_ref(), "Sprite")?;
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    let sprite = textures.try_map_zip(&path_id, |textures, &path_id| textures.sprite(path_id))?;
+    let (Some(old), Some(new)) = (sprite.old, sprite.new) else {
+        return Ok(out);
+    };
+    let sprite = OldNew::new(old, new);
+    let rect = sprite.as_ref().map(|(_, rect)| rect);
+    let data = sprite.as_ref().map(|(texture, _)| &texture.data);
+
+    if rect.changed() || data.changed() {
+        let image = sprite
+            .as_ref()
+            .try_map(|(texture, rect)| texture.image().map(|image| image.crop(rect)));
+        match image {
+            Ok(image) => compare_images(image.as_ref(), name, &mut out, files)?,
+            Err(e) => writeln!(out, "texture changed, could not decode: {e}")?,
+        }
+    }
+
+    Ok(out)
+}
+
+/// Summarizes an added or removed texture or sprite, and writes its image as `{name}.{version}.png`.
+pub(super) fn describe<P: TypeTreeProvider>(
+    textures: &Textures<'_, P>,
+    class_id: ClassId,
+    path_id: PathId,
+    name: &str,
+    version: &str,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<String> {
+    let (texture, rect) = if class_id == ClassId::Sprite {
+        match textures.sprite(path_id)? {
+            Some((texture, rect)) => (texture, Some(rect)),
+            None => return Ok(String::new()),
+        }
+    } else {
+        (textures.get(path_id)?, None)
+    };
+
+    let mut out = String::new();
+    if let Some(rect) = &rect {
+        write!(out, "{rect} of ")?;
+    }
+    write!(
+        out,
+        "{}×{} {}",
+        texture.width,
+        texture.height,
+        format_name(texture.format)
+    )?;
+    match (&rect, texture.mips) {
+        (Some(_), _) => write!(out, " texture")?,
+        (None, Some(mips)) => write!(out, ", {mips} mips")?,
+        (None, None) => {}
+    }
+    writeln!(out)?;
+
+    match texture.image() {
+        Ok(image) => match &rect {
+            Some(rect) => push_png(files, name, version, &image.crop(rect))?,
+            None => push_png(files, name, version, image)?,
+        },
+        Err(e) => writeln!(out, "could not decode: {e}")?,
+    }
+
+    Ok(out)
+}
+
+/// The fields of a sprite that decide how it is cut from its texture, leaving out the mesh.
+fn sprite_summary(value: &Value) -> Value {
+    let render_data = &value["m_RD"];
+    serde_json::json!({
+        "m_Rect": value["m_Rect"],
+        "m_Offset": value["m_Offset"],
+        "m_Border": value["m_Border"],
+        "m_Pivot": value["m_Pivot"],
+        "m_PixelsToUnits": value["m_PixelsToUnits"],
+        "m_RD": {
+            "texture": render_data["texture"],
+            "textureRect": render_data["textureRect"],
+        },
+    })
+}
+
+fn compare_images(
+    image: OldNew<&Image>,
+    name: &str,
+    out: &mut String,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<()> {
+    match highlight_changes(image) {
+        // e.g. only the mip levels changed
+        Some((_, 0)) => return Ok(()),
+        Some((diff, changed)) => {
+            let total = image.new.pixels.len();
+            writeln!(
+                out,
+                "{changed} of {total} pixels changed ({:.1}%)",
+                changed as f64 / total as f64 * 100.0
+            )?;
+            push_png(files, name, "diff", &diff)?;
+        }
+        // the size change is reported on its own
+        None => {}
+    }
+    push_png(files, name, "old", image.old)?;
+    push_png(files, name, "new", image.new)?;
+    Ok(())
+}
+
+fn push_png(
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    name: &str,
+    version: &str,
+    image: &Image,
+) -> Result<()> {
+    if image.pixels.is_empty() {
+        return Ok(());
+    }
+    files.push((format!("{name}.{version}.png").into(), image.png()?));
+    Ok(())
+}
+
+/// Marks changed pixels in red on top of a faded grayscale copy of the new image,
+/// and returns how many changed. `None` if the sizes differ.
+fn highlight_changes(image: OldNew<&Image>) -> Option<(Image, usize)> {
+    if (image.old.width, image.old.height) != (image.new.width, image.new.height) {
+        return None;
+    }
+
+    let mut changed = 0;
+    let pixels = image
+        .old
+        .pixels
+        .iter()
+        .zip(&image.new.pixels)
+        .map(|(old, new)| {
+            let delta = old.iter().zip(new).map(|(a, b)| a.abs_diff(*b)).max();
+            match delta {
+                Some(0) | None => {
+                    let [r, g, b, a] = new.map(u16::from);
+                    let gray = ((r * 3 + g * 6 + b) / 10) as u8;
+                    [gray, gray, gray, (a / 4) as u8]
+                }
+                Some(delta) => {
+                    changed += 1;
+                    [255, 0, 0, 128 + delta / 2]
+                }
+            }
+        })
+        .collect();
+
+    Some((
+        Image {
+            width: image.new.width,
+            height: image.new.height,
+            pixels,
+        },
+        changed,
+    ))
+}
+
+/// RGBA pixels, top row first.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+impl Image {
+    /// Unity stores the bottom row first.
+    fn from_bottom_up(width: usize, height: usize, pixels: Vec<[u8; 4]>) -> Image {
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks_exact(width).rev() {
+            flipped.extend_from_slice(row);
+        }
+        Image {
+            width,
+            height,
+            pixels: flipped,
+        }
+    }
+
+    /// `rect` is in texture coordinates, with the origin in the bottom left corner.
+    fn crop(&self, rect: &Rect) -> Image {
+        let clamp = |value: f32, max: usize| (value.round().max(0.0) as usize).min(max);
+        let left = clamp(rect.x, self.width);
+        let right = clamp(rect.x + rect.width, self.width).max(left);
+        let bottom = clamp(rect.y, self.height);
+        let top = clamp(rect.y + rect.height, self.height).max(bottom);
+
+        let pixels = (self.height - top..self.height - bottom)
+            .flat_map(|row| &self.pixels[row * self.width + left..row * self.width + right])
+            .copied()
+            .collect();
+        Image {
+            width: right - left,
+            height: top - bottom,
+            pixels,
+        }
+    }
+
+    fn png(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.pixels.as_flattened())?;
+        writer.finish()?;
+        Ok(out)
+    }
+}
+
+type BlockDecoder = dyn Fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>;
+
+/// Decodes the first mip level of a texture.
+fn decode(format: i32, data: &[u8], width: usize, height: usize) -> Result<Image> {
+    ensure!(width > 0 && height > 0, "empty texture");
+    let len = width * height;
+
+    let raw = |bytes_per_pixel: usize, pixel: fn(&[u8]) -> [u8; 4]| -> Result<Vec<[u8; 4]>> {
+        let data = data
+            .get(..len * bytes_per_pixel)
+            .context("not enough image data")?;
+        Ok(data.chunks_exact(bytes_per_pixel).map(pixel).collect())
+    };
+    let block = |decode: &BlockDecoder| -> Result<Vec<[u8; 4]>> {
+        let mut image = vec![0u32; len];
+        decode(data, width, height, &mut image).map_err(|e| anyhow!(e))?;
+        Ok(image
+            .into_iter()
+            .map(|pixel| {
+                let [b, g, r, a] = pixel.to_le_bytes();
+                [r, g, b, a]
+            })
+            .collect())
+    };
+
+    use texture2ddecoder as t2d;
+    let pixels = match format {
+        // Alpha8
+        1 => raw(1, |p| [255, 255, 255, p[0]])?,
+        // RGB24
+        3 => raw(3, |p| [p[0], p[1], p[2], 255])?,
+        // RGBA32
+        4 => raw(4, |p| [p[0], p[1], p[2], p[3]])?,
+        // ARGB32
+        5 => raw(4, |p| [p[1], p[2], p[3], p[0]])?,
+        // RGB565
+        7 => raw(2, |p| {
+            let p = u16::from_le_bytes([p[0], p[1]]);
+            let scale = |value: u16, bits: u32| (value * 255 / ((1 << bits) - 1)) as u8;
+            [
+                scale(p >> 11, 5),
+                scale((p >> 5) & 0x3f, 6),
+                scale(p & 0x1f, 5),
+                255,
+            ]
+        })?,
+        // BGRA32
+        14 => raw(4, |p| [p[2], p[1], p[0], p[3]])?,
+        // RG16
+        62 => raw(2, |p| [p[0], p[1], 0, 255])?,
+        // R8
+        63 => raw(1, |p| [p[0], 0, 0, 255])?,
+        10 => block(&t2d::decode_bc1)?,
+        12 => block(&t2d::decode_bc3)?,
+        25 => block(&t2d::decode_bc7)?,
+        26 => block(&t2d::decode_bc4)?,
+        27 => block(&t2d::decode_bc5)?,
+        34 => block(&t2d::decode_etc1)?,
+        45 => block(&t2d::decode_etc2_rgb)?,
+        46 => block(&t2d::decode_etc2_rgba1)?,
+        47 => block(&t2d::decode_etc2_rgba8)?,
+        // ASTC_4x4 to ASTC_12x12, and the deprecated ASTC_RGBA variants
+        48..=59 => {
+            let size = [4, 5, 6, 8, 10, 12][(format - 48) as usize % 6];
+            block(&|data, width, height, image| {
+                t2d::decode_astc(data, width, height, size, size, image)
+            })?
+        }
+        // DXT1Crunched, DXT5Crunched, ETC_RGB4Crunched, ETC2_RGBA8Crunched
+        28 | 29 | 64 | 65 => {
+            // textures crunched before 2017.3 use the original crunch format
+            block(&t2d::decode_unity_crunch).or_else(|_| block(&t2d::decode_crunch))?
+        }
+        _ => bail!("unsupported format {}", format_name(format)),
+    };
+
+    Ok(Image::from_bottom_up(width, height, pixels))
+}
+
+/// See https://docs.unity3d.com/ScriptReference/TextureFormat.html
+fn format_name(format: i32) -> String {
+    let name = match format {
+        1 => "Alpha8",
+        2 => "ARGB4444",
+        3 => "RGB24",
+        4 => "RGBA32",
+        5 => "ARGB32",
+        7 => "RGB565",
+        9 => "R16",
+        10 => "DXT1",
+        12 => "DXT5",
+        13 => "RGBA4444",
+        14 => "BGRA32",
+        15 => "RHalf",
+        16 => "RGHalf",
+        17 => "RGBAHalf",
+        18 => "RFloat",
+        19 => "RGFloat",
+        20 => "RGBAFloat",
+        22 => "RGB9e5Float",
+        24 => "BC6H",
+        25 => "BC7",
+        26 => "BC4",
+        27 => "BC5",
+        28 => "DXT1Crunched",
+        29 => "DXT5Crunched",
+        30 => "PVRTC_RGB2",
+        31 => "PVRTC_RGBA2",
+        32 => "PVRTC_RGB4",
+        33 => "PVRTC_RGBA4",
+        34 => "ETC_RGB4",
+        41 => "EAC_R",
+        42 => "EAC_R_SIGNED",
+        43 => "EAC_RG",
+        44 => "EAC_RG_SIGNED",
+        45 => "ETC2_RGB",
+        46 => "ETC2_RGBA1",
+        47 => "ETC2_RGBA8",
+        48 | 54 => "ASTC_4x4",
+        49 | 55 => "ASTC_5x5",
+        50 | 56 => "ASTC_6x6",
+        51 | 57 => "ASTC_8x8",
+        52 | 58 => "ASTC_10x10",
+        53 | 59 => "ASTC_12x12",
+        62 => "RG16",
+        63 => "R8",
+        64 => "ETC_RGB4Crunched",
+        65 => "ETC2_RGBA8Crunched",
+        _ => return format!("TextureFormat({format})"),
+    };
+    name.to_owned()
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::Result;
+use rabex::files::serializedfile::SerializedType;
+use rabex::objects::PPtr;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rabex_env::handle::SerializedFileHandle;
+use rabex_env::resolver::BasedirEnvResolver;
+
+use crate::old_new::OldNew;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+enum TypeKey {
+    Class(String),
+    Script(String, String),
+    Ref(String),
+}
+
+/// Diffs the typetrees embedded in `m_Types` and `m_RefTypes`, independent of the objects using them.
+pub fn diff_types<R: BasedirEnvResolver, P: TypeTreeProvider>(
+    file: OldNew<&SerializedFileHandle<'_, R, P>>,
+) -> Result<String> {
+    let types = file.map(|file| {
+        collect_types(
+            &file.file.m_Types,
+            file.file.m_RefTypes.as_deref().unwrap_or_default(),
+            |ty| script_name(file, ty),
+        )
+    });
+    write_changes(types.as_ref())
+}
+
+fn write_changes(types: OldNew<&BTreeMap<TypeKey, (String, &TypeTreeNode)>>) -> Result<String> {
+    let changes = types.changes(|types| types.keys());
+
+    let mut text = String::new();
+    for key in changes.removed {
+        writeln!(&mut text, "--- Removed type {} ---", types.old[key].0)?;
+    }
+    for key in changes.added {
+        writeln!(&mut text, "--- Added type {} ---", types.new[key].0)?;
+    }
+    for key in changes.same {
+        let (label, node) = (&types.new[key].0, types.map(|types| types[key].1));
+
+        let mut schema = String::new();
+        diff_node(&mut schema, "", node)?;
+        if !schema.is_empty() {
+            writeln!(&mut text, "--- Changed schema of {label} ---")?;
+            text.push_str(&schema);
+        }
+    }
+
+    Ok(text)
+}
+
+/// Keys the types of a file by class, and scripts by their name or, if it can't be resolved, their hash.
+fn collect_types<'a>(
+    types: &'a [SerializedType],
+    ref_types: &'a [SerializedType],
+    script_name: impl Fn(&SerializedType) -> Option<String>,
+) -> BTreeMap<TypeKey, (String, &'a TypeTreeNode)> {
+    let mut collected = BTreeMap::new();
+
+    for ty in types {
+        let Some(node) = &ty.m_Type else { continue };
+        let class = format!("{:?}", ty.m_ClassID);
+        let (key, label) = match script_name(ty) {
+            Some(script) => (
+                TypeKey::Script(class.clone(), script.clone()),
+                format!("{class} {script}"),
+            ),
+            None if ty.m_ScriptID != [0; 16] => {
+                let hash = hex(&ty.m_ScriptID);
+                (
+                    TypeKey::Script(class.clone(), hash.clone()),
+                    format!("{class} {hash}"),
+                )
+            }
+            None => (TypeKey::Class(class.clone()), class),
+        };
+        collected.insert(key, (label, node));
+    }
+
+    for ty in ref_types {
+        let Some(node) = &ty.m_Type else { continue };
+        let name = match (ty.m_NameSpace.as_deref(), ty.m_ClassName.as_deref()) {
+            (Some(namespace), Some(class)) if !namespace.is_empty() => {
+                format!("{namespace}.{class}")
+            }
+            (_, Some(class)) => class.to_owned(),
+            _ => continue,
+        };
+        collected.insert(
+            TypeKey::Ref(name.clone()),
+            (format!("[SerializeReference] {name}"), node),
+        );
+    }
+
+    collected
+}
+
+fn script_name<R: BasedirEnvResolver, P: TypeTreeProvider>(
+    file: &SerializedFileHandle<'_, R, P>,
+    ty: &SerializedType,
+) -> Option<String> {
+    let index = usize::try_from(ty.m_ScriptTypeIndex).ok()?;
+    let id = file.file.m_ScriptTypes.as_ref()?.get(index)?;
+    let pptr = PPtr::new(id.m_LocalSerializedFileIndex, id.m_LocalIdentifierInFile);
+    let script = file
+        .deref(pptr.typed::<serde_json::Value>())
+        .ok()?
+        .read()
+        .ok()?;
+
+    let class = script.get("m_ClassName")?.as_str()?;
+    match script
+        .get("m_Namespace")
+        .and_then(serde_json::Value::as_str)
+    {
+        Some(namespace) if !namespace.is_empty() => Some(format!("{namespace}.{class}")),
+        _ => Some(class.to_owned()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn diff_node(w: &mut String, path: &str, node: OldNew<&TypeTreeNode>) -> Result<()> {
+    let children = node.map(|node| {
+        node.children
+            .iter()
+            .map(|child| (child.m_Name.as_str(), child))
+            .collect::<BTreeMap<_, _>>()
+    });
+    let changes = children
+        .as_ref()
+        .changes(|children| children.keys().copied());
+
+    for name in changes.removed {
+        writeln!(w, "- {path}.{name}: {}", children.old[name].m_Type)?;
+    }
+    for name in changes.added {
+        writeln!(w, "+ {path}.{name}: {}", children.new[name].m_Type)?;
+    }
+
+    let order = node.map(|node| {
+        node.children
+            .iter()
+            .map(|child| child.m_Name.as_str())
+            .filter(|name| changes.same.contains(name))
+            .collect::<Vec<_>>()
+    });
+    if order.changed() {
+        writeln!(
+            w,
+            "reordered {}: {} -> {}",
+            if path.is_empty() { "." } else { path },
+            order.old.join(", "),
+            order.new.join(", "),
+        )?;
+    }
+
+    for name in changes.same {
+        let child = children.as_ref().map(|children| children[name]);
+        let child_path = format!("{path}.{name}");
+        let ty = child.map(|child| child.m_Type.as_str());
+        if ty.changed() {
+            writeln!(w, "~ {child_path}: {} -> {}", ty.old, ty.new)?;
+        } else {
+            diff_node(w, &child_path, child)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rabex::objects::ClassId;
+
+    use super::*;
+
+    fn node(ty: &str, name: &str, children: Vec<TypeTreeNode>) -> TypeTreeNode {
+        TypeTreeNode {
+            m_Type: ty.to_owned(),
+            m_Name: name.to_owned(),
+            children,
+            ..Default::default()
+        }
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<TypeTreeNode> {
+        fields
+            .iter()
+            .map(|&(name, ty)| node(ty, name, Vec::new()))
+            .collect()
+    }
+
+    fn class(class_id: ClassId, children: Vec<TypeTreeNode>) -> SerializedType {
+        SerializedType {
+            m_ClassID: class_id,
+            m_ScriptTypeIndex: -1,
+            m_Type: Some(node(&format!("{class_id:?}"), "Base", children)),
+            ..Default::default()
+        }
+    }
+
+    fn script(index: i16, script_id: [u8; 16], children: Vec<TypeTreeNode>) -> SerializedType {
+        SerializedType {
+            m_ScriptTypeIndex: index,
+            m_ScriptID: script_id,
+            ..class(ClassId::MonoBehaviour, children)
+        }
+    }
+
+    fn reference(namespace: &str, name: &str, children: Vec<TypeTreeNode>) -> SerializedType {
+        SerializedType {
+            m_ClassName: Some(name.to_owned()),
+            m_NameSpace: Some(namespace.to_owned()),
+            m_Type: Some(node(name, "Base", children)),
+            ..Default::default()
+        }
+    }
+
+    fn diff(
+        types: OldNew<&[SerializedType]>,
+        ref_types: OldNew<&[SerializedType]>,
+        scripts: OldNew<&[&str]>,
+    ) -> String {
+        let types = types.map_zip(&ref_types, |types, ref_types| (types, *ref_types));
+        let collected = types.map_zip(&scripts, |(types, ref_types), scripts| {
+            collect_types(types, ref_types, |ty| {
+                let index = usize::try_from(ty.m_ScriptTypeIndex).ok()?;
+                Some(scripts[index].to_owned())
+            })
+        });
+        write_changes(collected.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn types() {
+        let position = || {
+            node(
+                "Vector3f",
+                "m_LocalPosition",
+                fields(&[("x", "float"), ("y", "float")]),
+            )
+        };
+        let old = [
+            class(
+                ClassId::Transform,
+                vec![
+                    position(),
+                    node("PPtr<Transform>", "m_Father", Vec::new()),
+                    node("vector", "m_Children", Vec::new()),
+                ],
+            ),
+            script(
+                0,
+                [1; 16],
+                fields(&[("health", "int"), ("speed", "float"), ("legacy", "string")]),
+            ),
+            script(1, [2; 16], fields(&[("rosaries", "int")])),
+            script(-1, [0xab; 16], fields(&[("a", "int")])),
+        ];
+        let mut position_xyz = position();
+        position_xyz.children.push(node("float", "z", Vec::new()));
+        let new = [
+            class(
+                ClassId::Transform,
+                vec![
+                    node("PPtr<Transform>", "m_Father", Vec::new()),
+                    position_xyz,
+                    node("vector", "m_Children", Vec::new()),
+                ],
+            ),
+            script(
+                0,
+                [1; 16],
+                fields(&[("health", "int"), ("speed", "double"), ("silk", "int")]),
+            ),
+            script(1, [3; 16], fields(&[("needles", "int")])),
+            script(-1, [0xab; 16], fields(&[("a", "SInt64")])),
+        ];
+        let old_refs = [reference("Game", "Tool", fields(&[("damage", "int")]))];
+        let new_refs = [
+            reference("Game", "Tool", fields(&[("damage", "int")])),
+            reference("", "Needle", fields(&[("length", "float")])),
+        ];
+
+        assert_eq!(
+            diff(
+                OldNew::new(&old[..], &new[..]),
+                OldNew::new(&old_refs[..], &new_refs[..]),
+                OldNew::new(
+                    &["HeroController", "OldScript"][..],
+                    &["HeroController", "NewScript"][..],
+                ),
+            ),
+            "--- Removed type MonoBehaviour OldScript ---\n\
+             --- Added type MonoBehaviour NewScript ---\n\
+             --- Added type [SerializeReference] Needle ---\n\
+             --- Changed schema of Transform ---\n\
+             reordered .: m_LocalPosition, m_Father, m_Children -> m_Father, m_LocalPosition, m_Children\n\
+             + .m_LocalPosition.z: float\n\
+             --- Changed schema of MonoBehaviour HeroController ---\n\
+             - .legacy: string\n\
+             + .silk: int\n\
+             ~ .speed: float -> double\n\
+             --- Changed schema of MonoBehaviour abababababababababababababababab ---\n\
+             ~ .a: int -> SInt64\n"
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let types = [
+            class(ClassId::GameObject, fields(&[("m_Name", "string")])),
+            script(0, [1; 16], fields(&[("health", "int")])),
+        ];
+        let scripts: &[&str] = &["HeroController"];
+        assert_eq!(
+            diff(
+                OldNew::new(&types[..], &types[..]),
+                OldNew::new(&[][..], &[][..]),
+                OldNew::new(scripts, scripts),
+            ),
+            ""
+        );
+    }
+}
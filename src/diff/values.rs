@@ -0,0 +1,213 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use anyhow::Result;
+use json_diff_ng::PathElement;
+use serde_json::Value;
+
+use crate::diff::Context;
+use crate::old_new::OldNew;
+
+/// Finds the outermost ancestor of `path` that is a known value type in both versions,
+/// and returns the length of its path and its rendering.
+pub fn collapse(path: &[PathElement], data: OldNew<&Value>) -> Option<(usize, OldNew<String>)> {
+    let mut value = data;
+    for len in 1..path.len() {
+        let element = &path[len - 1];
+        value = value
+            .try_map(|value| element.resolve(value).ok_or(()))
+            .ok()?;
+
+        let key = match element {
+            PathElement::Object(key) => *key,
+            PathElement::ArrayEntry(_) => "",
+        };
+        if let Ok(rendered) = value.try_map(|value| render(key, value).ok_or(())) {
+            return Some((len, rendered));
+        }
+    }
+    None
+}
+
+/// Renders vectors, quaternions, colors, rects, bounds and animation curves on a single line.
+pub fn render(key: &str, value: &Value) -> Option<String> {
+    let map = value.as_object()?;
+    let mut keys: Vec<&str> = map.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+
+    let field = |name: &str| map.get(name).and_then(Value::as_f64);
+    let fields = |names: &[&str]| {
+        names
+            .iter()
+            .map(|&name| field(name))
+            .collect::<Option<Vec<_>>>()
+    };
+
+    match keys.as_slice() {
+        ["x", "y"] => Some(tuple(&fields(&["x", "y"])?)),
+        ["x", "y", "z"] => Some(tuple(&fields(&["x", "y", "z"])?)),
+        ["w", "x", "y", "z"] => {
+            let [x, y, z, w] = fields(&["x", "y", "z", "w"])?[..] else {
+                return None;
+            };
+            if key.to_ascii_lowercase().contains("rotation") {
+                Some(format!("euler{}", tuple(&euler_angles(x, y, z, w))))
+            } else {
+                Some(tuple(&[x, y, z, w]))
+            }
+        }
+        ["a", "b", "g", "r"] => Some(color(&fields(&["r", "g", "b", "a"])?)),
+        ["height", "width", "x", "y"] => {
+            let [x, y, width, height] = fields(&["x", "y", "width", "height"])?[..] else {
+                return None;
+            };
+            Some(format!(
+                "({}, {}, {}×{})",
+                number(x),
+                number(y),
+                number(width),
+                number(height)
+            ))
+        }
+        ["m_Center", "m_Extent"] => Some(format!(
+            "center {} extent {}",
+            render("", &map["m_Center"])?,
+            render("", &map["m_Extent"])?,
+        )),
+        [
+            "m_Curve",
+            "m_PostInfinity",
+            "m_PreInfinity",
+            "m_RotationOrder",
+        ] => animation_curve(map["m_Curve"].as_array()?),
+        _ => None,
+    }
+}
+
+/// Renders any value on a single line, like a parameter or keyframe value.
+/// Qualified PPtrs are shown as their target.
+pub fn render_any(value: &Value) -> String {
+    match value {
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => self::number(float),
+            _ => number.to_string(),
+        },
+        Value::String(string) => format!("{string:?}"),
+        Value::Object(map) if map.contains_key("$target") => match &map["$target"] {
+            Value::String(target) => target.clone(),
+            other => other.to_string(),
+        },
+        Value::Object(_) => render("", value).unwrap_or_else(|| value.to_string()),
+        _ => value.to_string(),
+    }
+}
+
+/// The array at `key` or at a JSON pointer like `/m_Tags/tags`, empty if it is missing
+pub fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    let array = match key.starts_with('/') {
+        true => value.pointer(key),
+        false => value.get(key),
+    };
+    array.and_then(Value::as_array).map_or(&[], Vec::as_slice)
+}
+
+/// The entries of a serialized map, which is a list of `first`/`second` pairs
+pub fn pairs<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = (&'a Value, &'a Value)> {
+    array(value, key)
+        .iter()
+        .filter_map(|pair| Some((pair.get("first")?, pair.get("second")?)))
+}
+
+/// The string at `key`, empty if it is missing
+pub fn string<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Diffs the fields not already diffed by their structure as JSON.
+pub fn diff_json_without(
+    cx: &Context,
+    mut value: OldNew<Value>,
+    fields: &[&str],
+    class: &str,
+) -> Result<String> {
+    for value in [&mut value.old, &mut value.new] {
+        if let Some(map) = value.as_object_mut() {
+            for field in fields {
+                map.remove(*field);
+            }
+        }
+    }
+    crate::diff::diff_json(cx, value.as_ref(), class)
+}
+
+/// Writes the added and removed entries of a set, like keywords or dependencies.
+pub fn diff_set(out: &mut String, kind: &str, set: OldNew<&BTreeSet<String>>) -> Result<()> {
+    let changes = set.changes(|set| set.iter());
+    for entry in changes.removed {
+        writeln!(out, "{kind} '{entry}' removed")?;
+    }
+    for entry in changes.added {
+        writeln!(out, "{kind} '{entry}' added")?;
+    }
+    Ok(())
+}
+
+pub fn number(value: f64) -> String {
+    // typetree floats are single precision, printing them as such avoids noise like 0.10000000149011612
+    (value as f32).to_string()
+}
+
+fn tuple(values: &[f64]) -> String {
+    let values: Vec<_> = values.iter().map(|&value| number(value)).collect();
+    format!("({})", values.join(", "))
+}
+
+fn color(rgba: &[f64]) -> String {
+    if rgba.iter().all(|c| (0.0..=1.0).contains(c)) {
+        let mut hex = String::from("#");
+        for c in rgba {
+            let _ = write!(hex, "{:02X}", (c * 255.0).round() as u8);
+        }
+        hex
+    } else {
+        // HDR colors don't fit into hex notation
+        format!("rgba{}", tuple(rgba))
+    }
+}
+
+/// Converts a quaternion to euler angles in degrees, in the same convention as `Quaternion.eulerAngles`.
+fn euler_angles(x: f64, y: f64, z: f64, w: f64) -> [f64; 3] {
+    let pitch = (2.0 * (w * x - y * z)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * y + x * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let roll = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (x * x + z * z));
+
+    [pitch, yaw, roll].map(|angle| {
+        let degrees = angle.to_degrees().rem_euclid(360.0);
+        // round to avoid printing 359.99998 for 0
+        let rounded = (degrees * 1000.0).round() / 1000.0;
+        if rounded == 360.0 { 0.0 } else { rounded }
+    })
+}
+
+fn animation_curve(keys: &[Value]) -> Option<String> {
+    const MAX_KEYS: usize = 8;
+
+    let mut out = format!("{} keys [", keys.len());
+    for (i, key) in keys.iter().take(MAX_KEYS).enumerate() {
+        let time = key.get("time")?.as_f64()?;
+        let value = key.get("value")?;
+        let value = match value.as_f64() {
+            Some(value) => number(value),
+            None => render("", value)?,
+        };
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}: {}", number(time), value);
+    }
+    if keys.len() > MAX_KEYS {
+        out.push_str(", ...");
+    }
+    out.push(']');
+    Some(out)
+}
@@ -1,8 +1,11 @@
 #![feature(str_split_whitespace_remainder)]
 use std::collections::HashSet;
+use std::fmt::Write as _;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use anstream::eprintln;
 use anyhow::{Context as _, Result, anyhow, bail, ensure};
 use clap::Parser;
 use rabex::objects::ClassId;
@@ -13,12 +16,18 @@ use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use regex::Regex;
 
 use crate::depotdownloader_manifest::Manifest;
-use crate::diff::Context;
+use crate::diff::unity::ScriptFieldChanges;
+use crate::diff::{Context, Differ};
 use crate::old_new::OldNew;
+use crate::progress::Progress;
+use crate::run_log::RunLog;
 
 mod depotdownloader_manifest;
+mod deps;
 mod diff;
 mod old_new;
+mod progress;
+mod run_log;
 
 pub fn find_single_file_of_extension(folder: &Path, extension: &str) -> Result<PathBuf> {
     let entries = std::fs::read_dir(folder)?;
@@ -39,6 +48,14 @@ pub fn find_single_file_of_extension(folder: &Path, extension: &str) -> Result<P
 struct App {
     manifests: Vec<ManifestFiles>,
 }
+impl App {
+    fn manifest(&self, id: &str) -> Result<&ManifestFiles> {
+        self.manifests
+            .iter()
+            .find(|m| m.manifest.id == id)
+            .context(format!("manifest {} does not exist", id))
+    }
+}
 struct ManifestFiles {
     path: PathBuf,
     manifest: Manifest,
@@ -74,9 +91,75 @@ enum Command {
         out_dir: PathBuf,
         manifest_old: Option<String>,
         manifest_new: Option<String>,
+        /// Exit with an error if any file failed to diff
+        #[clap(long)]
+        strict: bool,
+        /// Only print failures and the final summary line
+        #[clap(long, short, conflicts_with = "verbose")]
+        quiet: bool,
+        /// Also list files whose diff came out empty or was reused
+        #[clap(long, short)]
+        verbose: bool,
+        /// Delete the output directory instead of reusing up-to-date results of a previous run
+        #[clap(long)]
+        clean: bool,
+        /// Extract changed, added and removed audio clips next to the diff, as WAV or Ogg.
+        /// Clips in other formats like ADPCM are written as the raw FSB5 bank.
+        #[clap(long)]
+        extract_audio: bool,
+        /// Write SVG overlays of the old and new shape of changed 2D colliders
+        #[clap(long)]
+        collider_svg: bool,
+        /// Match the elements of arrays whose `{class}{path}` matches REGEX by the field KEY,
+        /// e.g. `^MonoBehaviour\.items$=itemId`. An empty KEY matches them by position.
+        /// Can be repeated, the first match wins.
+        #[clap(long, value_name = "REGEX=KEY", value_parser = parse_array_key)]
+        array_key: Vec<(Regex, String)>,
+    },
+    /// Print the dependency graph between the Addressables bundles of a manifest
+    Deps {
+        /// Defaults to the latest manifest
+        manifest: Option<String>,
+        /// Print the changes since this manifest instead of the graph
+        #[clap(long)]
+        since: Option<String>,
+        #[clap(long, value_enum, default_value_t = GraphFormat::Dot, conflicts_with = "since")]
+        format: GraphFormat,
+        /// Only list the bundles that neither an asset nor another bundle depends on
+        #[clap(long, conflicts_with = "since")]
+        orphans: bool,
     },
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Flags of the diff command that don't select what is diffed
+struct DiffOptions {
+    verbosity: Verbosity,
+    /// Delete the output directory instead of reusing results of a previous run
+    clean: bool,
+    extract_audio: bool,
+    collider_svg: bool,
+    /// Passed as [`diff::ArrayKeys::overrides`]
+    array_keys: Vec<(Regex, String)>,
+}
+
+fn parse_array_key(arg: &str) -> Result<(Regex, String)> {
+    let (regex, key) = arg.rsplit_once('=').context("expected REGEX=KEY")?;
+    Ok((Regex::new(regex)?, key.to_owned()))
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let app = load()?;
@@ -95,7 +178,20 @@ fn main() -> Result<()> {
             out_dir,
             manifest_old,
             manifest_new,
+            strict,
+            quiet,
+            verbose,
+            clean,
+            extract_audio,
+            collider_svg,
+            array_key,
         }) => {
+            let verbosity = match (quiet, verbose) {
+                (true, _) => Verbosity::Quiet,
+                (_, true) => Verbosity::Verbose,
+                _ => Verbosity::Normal,
+            };
+
             let manifest = match (manifest_old, manifest_new) {
                 (Some(old), Some(new)) => OldNew::new(old, new),
                 (Some(new), None) => {
@@ -120,32 +216,118 @@ fn main() -> Result<()> {
                 (None, Some(_)) => unreachable!(),
             };
 
-            let files = manifest.try_map(|id| {
-                app.manifests
-                    .iter()
-                    .find(|m| m.manifest.id == id)
-                    .context(format!("manifest {} does not exist", id))
-            })?;
+            let files = manifest.try_map(|id| app.manifest(&id))?;
             let out_dir = out_dir.join(format!(
                 "{} to {}",
                 files.old.manifest.date.date(),
                 files.new.manifest.date.date()
             ));
 
-            println!("Diffing {} -> {}", files.old.manifest, files.new.manifest);
+            if verbosity >= Verbosity::Normal {
+                println!("Diffing {} -> {}", files.old.manifest, files.new.manifest);
+            }
 
             let start = Instant::now();
-            diff(files, &out_dir).context("Failed to generate diff")?;
-            println!("Diffed all files in {:?}", start.elapsed())
+            let options = DiffOptions {
+                verbosity,
+                clean,
+                extract_audio,
+                collider_svg,
+                array_keys: array_key,
+            };
+            let failures = diff(files, &out_dir, &options).context("Failed to generate diff")?;
+            println!("Diffed all files in {:?}", start.elapsed());
+
+            ensure!(
+                !strict || failures == 0,
+                "{failures} files failed to diff, see {}",
+                out_dir.join("errors.txt").display()
+            );
+        }
+        Some(Command::Deps {
+            manifest,
+            since,
+            format,
+            orphans,
+        }) => {
+            let manifest = match manifest {
+                Some(id) => app.manifest(&id)?,
+                None => app
+                    .manifests
+                    .last()
+                    .context("No downloaded manifests found")?,
+            };
+            let tpk = TypeTreeCache::new(TpkTypeTreeBlob::embedded());
+            let graph = |files: &ManifestFiles| -> Result<deps::Graph> {
+                let env = Environment::new_in(&files.path, &tpk)?;
+                deps::Graph::build(
+                    &files.path,
+                    files.manifest.files.keys().map(String::as_str),
+                    env.unity_version()?,
+                )
+                .with_context(|| format!("Failed to build dependency graph of {}", files.manifest))
+            };
+
+            let new = graph(manifest)?;
+            if let Some(since) = since {
+                let old = graph(app.manifest(&since)?)?;
+                print!("{}", deps::diff(OldNew::new(&old, &new))?);
+            } else if orphans {
+                for bundle in new.orphans() {
+                    println!("{bundle}");
+                }
+            } else {
+                match format {
+                    GraphFormat::Dot => print!("{}", new.to_dot()?),
+                    GraphFormat::Json => println!("{}", new.to_json()?),
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-fn diff(manifest_files: OldNew<&ManifestFiles>, diff_out_dir: &Path) -> Result<()> {
-    let _ = std::fs::remove_dir_all(diff_out_dir);
+enum Status {
+    Changed,
+    Empty,
+    UpToDate,
+    Failed(anyhow::Error),
+}
+
+struct FileReport<'a> {
+    path: &'a str,
+    differ: Option<Differ>,
+    flags: Option<OldNew<u32>>,
+    status: Option<Status>,
+    duration: Duration,
+    size: usize,
+    warnings: Vec<String>,
+}
+impl FileReport<'_> {
+    fn failure(&self) -> Option<&anyhow::Error> {
+        match &self.status {
+            Some(Status::Failed(error)) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the number of files that failed to diff.
+fn diff(
+    manifest_files: OldNew<&ManifestFiles>,
+    diff_out_dir: &Path,
+    options: &DiffOptions,
+) -> Result<usize> {
+    let verbosity = options.verbosity;
+    if options.clean {
+        let _ = std::fs::remove_dir_all(diff_out_dir);
+    }
     std::fs::create_dir_all(diff_out_dir)?;
+    for summary in ["errors.txt", "script_fields.diff"] {
+        let _ = std::fs::remove_file(diff_out_dir.join(summary));
+    }
+    let run_log = RunLog::open(diff_out_dir)?;
 
     let tpk = TypeTreeCache::new(TpkTypeTreeBlob::embedded());
     let unity_game = manifest_files
@@ -156,94 +338,310 @@ fn diff(manifest_files: OldNew<&ManifestFiles>, diff_out_dir: &Path) -> Result<(
         // file_filter: "dataassets".into(),
         // file_filter: ".dll".into(),
         file_filter: "".into(),
+        root_dir: manifest_files.map(|files| files.path.as_path()),
         text_diff_context_size: 6,
 
-        json_ignore_regex: Some(Regex::new("m_PreloadTable|preloadIndex|m_glyphInfoList").unwrap()),
+        json_ignore_regex: Some(Regex::new("m_glyphInfoList").unwrap()),
         json_ignore_new_default: true,
         json_sort: false,
+        json_float_tolerance: Some(diff::FloatTolerance {
+            default: diff::Tolerance {
+                absolute: 1e-5,
+                relative: 1e-6,
+            },
+            overrides: vec![(
+                Regex::new(r"^(Rect)?Transform\.m_Local").unwrap(),
+                diff::Tolerance {
+                    absolute: 1e-4,
+                    relative: 0.0,
+                },
+            )],
+        }),
+        json_array_keys: Some(diff::ArrayKeys {
+            default: ["m_Name", "$target", "id", "name", "first"]
+                .map(String::from)
+                .to_vec(),
+            overrides: options.array_keys.clone(),
+        }),
 
         cs_decompile_assembly: true,
 
         unity_game,
         unity_filter: diff::unity::Filter {
             ignore_classes: HashSet::from_iter([
-                ClassId::Texture2D,
-                ClassId::Sprite,
-                ClassId::SpriteAtlas,
                 ClassId::SpriteRenderer,
                 ClassId::AudioSource,
-                ClassId::CircleCollider2D,
                 ClassId::HingeJoint2D,
             ]),
         },
+        unity_schema_diff: true,
+        unity_dump_objects: Some(diff::unity::DumpLimits {
+            max_depth: 4,
+            max_size: 64 * 1024,
+        }),
+        unity_extract_audio: options.extract_audio,
+        unity_collider_svg: options.collider_svg,
     };
+    let script_fields = Mutex::new(ScriptFieldChanges::default());
 
     let file_changes = manifest_files.changes(|files| files.manifest.files.keys());
 
-    if !file_changes.removed.is_empty() {
-        println!("Removed {} files:", file_changes.removed.len());
-        for file in &file_changes.removed {
-            println!("- {}", file);
+    if verbosity >= Verbosity::Normal {
+        if !file_changes.removed.is_empty() {
+            println!("Removed {} files:", file_changes.removed.len());
+            for file in &file_changes.removed {
+                println!("- {}", file);
+            }
         }
-    }
-    if !file_changes.added.is_empty() {
-        println!("Added {} files:", file_changes.added.len());
-        for file in &file_changes.added {
-            println!("- {}", file);
+        if !file_changes.added.is_empty() {
+            println!("Added {} files:", file_changes.added.len());
+            for file in &file_changes.added {
+                println!("- {}", file);
+            }
         }
     }
 
-    file_changes
+    let candidates: Vec<&str> = file_changes
         .same
+        .into_iter()
+        .map(String::as_str)
+        .filter(|path| path.contains(&cx.file_filter))
+        .filter(|path| {
+            let file = manifest_files.map(|x| &x.manifest.files[*path]);
+            file.map(|file| file.flags).changed() || file.map(|file| &file.sha).changed()
+        })
+        .collect();
+
+    let progress = Progress::new(candidates.len(), verbosity >= Verbosity::Normal);
+    let mut reports: Vec<FileReport> = candidates
         .into_par_iter()
         .map(|path| {
             let manifest_file = manifest_files.map(|x| &x.manifest.files[path]);
+            let flags = manifest_file
+                .map(|file| file.flags)
+                .consume(|flags| flags.changed().then_some(flags));
 
-            if !path.contains(&cx.file_filter) {
-                return Ok(());
-            }
+            let differ = Differ::detect(&cx, Path::new(path)).ok();
 
-            if manifest_file.map(|file| file.flags).changed() {
-                println!(
-                    "Changed '{path}' flags from {:b} to {:b}",
-                    manifest_file.old.flags, manifest_file.new.flags
-                );
-            }
-            if manifest_file.map(|file| &file.sha).changed() {
-                let start = Instant::now();
-
-                let diff_out_file = diff_out_dir.join(path);
-
-                let data = manifest_files.try_map(|f| std::fs::read(f.path.join(path)))?;
-                let diff = diff::diff(&cx, Path::new(path), data.as_deref())?;
-
-                if !diff.content.is_empty() {
-                    let mut out_file = diff_out_file.clone();
-                    if let Some(extension) = diff.extension {
-                        out_file.add_extension(extension);
-                    }
-                    std::fs::create_dir_all(diff_out_file.parent().unwrap())?;
-                    std::fs::write(&out_file, &diff.content)?;
-                    println!("Changed '{path}' ({}ms)", start.elapsed().as_millis());
+            let start = Instant::now();
+            let sha = manifest_file.map(|file| file.sha.as_str());
+            let status = sha.changed().then(|| {
+                let fingerprint = differ.map_or_else(String::new, |differ| cx.fingerprint(differ));
+                if let Some(entry) = run_log.up_to_date(path, sha, &fingerprint) {
+                    script_fields.lock().unwrap().merge(&entry.script_fields);
+                    return (Status::UpToDate, entry.size, entry.warnings.clone());
                 }
-                for (child_path, child) in &diff.children {
-                    ensure!(
-                        diff.extension.is_some(),
-                        "Internal error: Can't have diff with children and no extension"
-                    );
-                    let mut out_file = diff_out_file.join(child_path);
-                    if let Some(extension) = child.extension {
-                        out_file.add_extension(extension);
-                    }
-                    std::fs::create_dir_all(out_file.parent().unwrap())?;
-                    std::fs::write(&out_file, &child.content)
-                        .with_context(|| format!("Failed to save diff {}", out_file.display()))?;
+
+                let result = run_log.remove_outputs(path).and_then(|()| {
+                    let file_diff =
+                        catch_panic(|| diff_file(&cx, manifest_files, diff_out_dir, path))?;
+                    let entry = run_log::Entry {
+                        path: path.to_owned(),
+                        old_sha: sha.old.to_owned(),
+                        new_sha: sha.new.to_owned(),
+                        fingerprint,
+                        size: file_diff.size,
+                        outputs: file_diff.outputs,
+                        script_fields: file_diff.script_fields,
+                        warnings: file_diff.warnings,
+                    };
+                    run_log.record(&entry)?;
+                    script_fields.lock().unwrap().merge(&entry.script_fields);
+                    Ok((entry.size, entry.warnings))
+                });
+                match result {
+                    Ok((0, warnings)) => (Status::Empty, 0, warnings),
+                    Ok((size, warnings)) => (Status::Changed, size, warnings),
+                    Err(error) => (Status::Failed(error), 0, Vec::new()),
                 }
-            }
+            });
+            progress.inc();
 
-            Ok(())
+            let (status, size, warnings) = match status {
+                Some((status, size, warnings)) => (Some(status), size, warnings),
+                None => (None, 0, Vec::new()),
+            };
+            FileReport {
+                path,
+                differ,
+                flags,
+                status,
+                duration: start.elapsed(),
+                size,
+                warnings,
+            }
         })
-        .collect::<Result<()>>()?;
+        .collect();
+    progress.finish();
+    reports.sort_by_key(|report| report.path);
 
-    Ok(())
+    if verbosity >= Verbosity::Normal {
+        print_summary(&reports, verbosity);
+    }
+
+    let script_fields = script_fields.into_inner().unwrap();
+    if !script_fields.is_empty() {
+        let mut text = String::new();
+        script_fields.write(&mut text)?;
+        std::fs::write(diff_out_dir.join("script_fields.diff"), text)?;
+        if verbosity > Verbosity::Quiet {
+            println!(
+                "Changed serialized fields of {} scripts",
+                script_fields.len()
+            );
+        }
+    }
+
+    let failures: Vec<_> = reports
+        .iter()
+        .filter_map(|report| Some((report, report.failure()?)))
+        .collect();
+    if !failures.is_empty() {
+        let mut errors = String::new();
+        for (report, error) in &failures {
+            let differ = report.differ.map_or("unknown", Differ::name);
+            writeln!(&mut errors, "=== {} ({differ}) ===", report.path)?;
+            writeln!(&mut errors, "{:?}\n", error)?;
+        }
+        std::fs::write(diff_out_dir.join("errors.txt"), errors)?;
+
+        let style = diff::error_style();
+        eprintln!("{style}Failed to diff {} files:{style:#}", failures.len());
+        for (report, error) in &failures {
+            eprintln!("{style}- {}: {:#}{style:#}", report.path, error);
+        }
+    }
+
+    let changed = reports
+        .iter()
+        .filter(|report| matches!(report.status, Some(Status::Changed)))
+        .count();
+    let up_to_date = reports
+        .iter()
+        .filter(|report| matches!(report.status, Some(Status::UpToDate)))
+        .count();
+    println!(
+        "{changed} files changed, {up_to_date} up to date, {} failed, {} added, {} removed",
+        failures.len(),
+        file_changes.added.len(),
+        file_changes.removed.len(),
+    );
+
+    Ok(failures.len())
+}
+
+fn print_summary(reports: &[FileReport], verbosity: Verbosity) {
+    println!(
+        "{:<8} {:<14} {:>8} {:>8}  path",
+        "status", "differ", "time", "size"
+    );
+    for report in reports {
+        let status = match &report.status {
+            Some(Status::Changed) => "changed",
+            Some(Status::Empty) if verbosity >= Verbosity::Verbose => "empty",
+            Some(Status::UpToDate) if verbosity >= Verbosity::Verbose => "reused",
+            Some(Status::Failed(_)) => "failed",
+            Some(Status::Empty | Status::UpToDate) | None if report.flags.is_some() => "flags",
+            Some(Status::Empty) if !report.warnings.is_empty() => "empty",
+            Some(Status::Empty | Status::UpToDate) | None => continue,
+        };
+        print!(
+            "{:<8} {:<14} {:>8} {:>8}  {}",
+            status,
+            report.differ.map_or("unknown", Differ::name),
+            progress::format_duration(report.duration),
+            progress::format_size(report.size),
+            report.path,
+        );
+        if let Some(flags) = report.flags {
+            print!(" (flags {:b} -> {:b})", flags.old, flags.new);
+        }
+        println!();
+
+        let style = diff::warn_style();
+        for warning in &report.warnings {
+            anstream::println!("{style}         warning: {warning}{style:#}");
+        }
+    }
+}
+
+/// Turns a panic of a differ into an error, so that it is reported like any other failure
+/// instead of aborting the whole run.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_owned());
+        Err(anyhow!("panicked: {message}"))
+    })
+}
+
+struct FileDiff {
+    /// Total size of the written diff files
+    size: usize,
+    /// Paths of the written diff files relative to the output directory
+    outputs: Vec<PathBuf>,
+    warnings: Vec<String>,
+    script_fields: ScriptFieldChanges,
+}
+
+fn diff_file(
+    cx: &Context,
+    manifest_files: OldNew<&ManifestFiles>,
+    diff_out_dir: &Path,
+    path: &str,
+) -> Result<FileDiff> {
+    let diff_out_file = diff_out_dir.join(path);
+
+    let data = manifest_files.try_map(|f| std::fs::read(f.path.join(path)))?;
+    let diff = diff::diff(cx, Path::new(path), data.as_deref())?;
+
+    let mut size = 0;
+    let mut outputs = Vec::new();
+    if !diff.content.is_empty() {
+        let mut out_file = diff_out_file.clone();
+        if let Some(extension) = diff.extension {
+            out_file.add_extension(extension);
+        }
+        std::fs::create_dir_all(diff_out_file.parent().unwrap())?;
+        std::fs::write(&out_file, &diff.content)?;
+        size += diff.content.len();
+        outputs.push(out_file.strip_prefix(diff_out_dir)?.to_owned());
+    }
+    for (child_path, child) in &diff.children {
+        ensure!(
+            diff.extension.is_some(),
+            "Internal error: Can't have diff with children and no extension"
+        );
+        let mut out_file = diff_out_file.join(child_path);
+        if let Some(extension) = child.extension {
+            out_file.add_extension(extension);
+        }
+        std::fs::create_dir_all(out_file.parent().unwrap())?;
+        std::fs::write(&out_file, &child.content)
+            .with_context(|| format!("Failed to save diff {}", out_file.display()))?;
+        size += child.content.len();
+        outputs.push(out_file.strip_prefix(diff_out_dir)?.to_owned());
+    }
+    for (file_path, content) in &diff.files {
+        ensure!(
+            diff.extension.is_some(),
+            "Internal error: Can't have diff with files and no extension"
+        );
+        let out_file = diff_out_file.join(file_path);
+        std::fs::create_dir_all(out_file.parent().unwrap())?;
+        std::fs::write(&out_file, content)
+            .with_context(|| format!("Failed to save {}", out_file.display()))?;
+        size += content.len();
+        outputs.push(out_file.strip_prefix(diff_out_dir)?.to_owned());
+    }
+
+    Ok(FileDiff {
+        size,
+        outputs,
+        warnings: diff.warnings,
+        script_fields: diff.script_fields,
+    })
 }
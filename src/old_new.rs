@@ -2,7 +2,9 @@
 use std::collections::BTreeSet;
 use std::ops::Deref;
 
-#[derive(Debug, Default, Clone, Copy)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct OldNew<T> {
     pub old: T,
     pub new: T,
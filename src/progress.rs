@@ -0,0 +1,90 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Single-line progress bar on stderr.
+/// Only drawn when stderr is a terminal, so CI logs stay stable.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+    enabled: bool,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+const WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Progress {
+    pub fn new(total: usize, enabled: bool) -> Self {
+        Progress {
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+            enabled: enabled && std::io::stderr().is_terminal(),
+            last_draw: Mutex::new(None),
+        }
+    }
+
+    pub fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.enabled {
+            return;
+        }
+
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if last_draw.is_some_and(|last| last.elapsed() < REDRAW_INTERVAL) && done < self.total {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+
+        let filled = (WIDTH * done).checked_div(self.total).unwrap_or(WIDTH);
+        let remaining = self.total.saturating_sub(done);
+        let eta = self.start.elapsed().mul_f64(remaining as f64 / done as f64);
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K[{}{}] {done}/{} files, {remaining} remaining, ETA {}",
+            "=".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            self.total,
+            format_duration(eta),
+        );
+        let _ = stderr.flush();
+    }
+
+    pub fn finish(&self) {
+        if self.enabled {
+            let _ = write!(std::io::stderr(), "\r\x1b[2K");
+        }
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else if secs > 0 {
+        format!("{}.{}s", secs, duration.subsec_millis() / 100)
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+pub fn format_size(size: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "K", "M", "G"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size}{}", UNITS[unit])
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::diff::unity::ScriptFieldChanges;
+use crate::old_new::OldNew;
+
+/// A file that was diffed successfully in a previous run.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub path: String,
+    pub old_sha: String,
+    pub new_sha: String,
+    pub fingerprint: String,
+    pub size: usize,
+    /// Written diff files, relative to the output directory
+    pub outputs: Vec<PathBuf>,
+    /// Replayed into the run's script field report when the result is reused
+    #[serde(default)]
+    pub script_fields: ScriptFieldChanges,
+    /// Reported again when the result is reused
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Append-only log of completed files in an output directory.
+/// Every line is written as soon as a file is done, so an interrupted run can be resumed.
+/// Files diffed again get another line, which is dropped when the log is compacted on the next open.
+pub struct RunLog {
+    out_dir: PathBuf,
+    entries: HashMap<String, Entry>,
+    file: Mutex<File>,
+}
+
+impl RunLog {
+    pub const FILE_NAME: &str = "run.jsonl";
+
+    pub fn open(out_dir: &Path) -> Result<RunLog> {
+        let path = out_dir.join(RunLog::FILE_NAME);
+
+        let mut entries = HashMap::new();
+        let mut lines = 0;
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                lines += 1;
+                // the last line may be truncated if the previous run was killed
+                let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+                    continue;
+                };
+                entries.insert(entry.path.clone(), entry);
+            }
+        }
+
+        // one line per path, the last entry of a path wins
+        if lines > entries.len() {
+            let mut sorted: Vec<&Entry> = entries.values().collect();
+            sorted.sort_by_key(|entry| &entry.path);
+            let mut compacted = String::new();
+            for entry in sorted {
+                compacted.push_str(&serde_json::to_string(entry)?);
+                compacted.push('\n');
+            }
+            let tmp = path.with_extension("jsonl.tmp");
+            std::fs::write(&tmp, compacted)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(RunLog {
+            out_dir: out_dir.to_owned(),
+            entries,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Returns the previous result if it was computed from the same inputs and config, and its outputs still exist.
+    pub fn up_to_date(&self, path: &str, sha: OldNew<&str>, fingerprint: &str) -> Option<&Entry> {
+        let entry = self.entries.get(path)?;
+        let up_to_date = entry.old_sha == sha.old
+            && entry.new_sha == sha.new
+            && entry.fingerprint == fingerprint
+            && entry
+                .outputs
+                .iter()
+                .all(|output| self.out_dir.join(output).is_file());
+        up_to_date.then_some(entry)
+    }
+
+    /// Removes the outputs of the previous result for `path`, before it gets recomputed.
+    pub fn remove_outputs(&self, path: &str) -> Result<()> {
+        let Some(entry) = self.entries.get(path) else {
+            return Ok(());
+        };
+        for output in &entry.outputs {
+            match std::fs::remove_file(self.out_dir.join(output)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}